@@ -2,19 +2,53 @@ use eframe::egui;
 use eframe::egui::{widgets, CentralPanel, SidePanel, TopBottomPanel};
 use egui::{FontFamily, FontId, TextStyle};
 
+use itertools::Itertools;
 use poll_promise::Promise;
 use rayon::prelude::*;
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+mod blobs;
+mod calibration;
+mod detect;
+mod diffcache;
+mod editor;
+mod exclusion;
+mod export;
 mod font;
+mod imagej;
 mod imagestack;
+mod pairs;
+mod plate;
 mod process;
+mod qc;
 mod roi;
+mod schema;
+mod summary;
+mod threshold;
+mod timeline;
 mod toggle;
 
 type Cache = Option<(usize, image::ImageBuffer<image::Rgba<u8>, Vec<u8>>)>;
+/// Areas, blob statistics (empty unless requested) and areas at each
+/// threshold of the sweep of the ROIs of one collection.
+type Measurement = (Vec<u32>, Vec<blobs::BlobStats>, Vec<Vec<u32>>);
+/// Threshold, measurements and quality control (first lag only, if
+/// requested) of a pair of frames, `None` when it touches an excluded frame.
+type PairResult = Option<(f64, Vec<Measurement>, Option<qc::FrameQc>)>;
+/// Group means of a run and its pairs flagged by the quality control.
+type RunOutput = Result<(Option<summary::GroupSummary>, Vec<qc::FrameQc>), String>;
+/// Images and frame pairs of a fixed-scale noise calibration.
+type NoiseKey = (Arc<[std::path::PathBuf]>, Vec<(usize, usize)>);
+/// Settings a threshold selected once per run depends on.
+type ThresholdKey = (
+    NoiseKey,
+    process::Algorithm,
+    process::Normalizer,
+    roi::Direction,
+    threshold::AutoThreshold,
+);
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -22,10 +56,56 @@ type Cache = Option<(usize, image::ImageBuffer<image::Rgba<u8>, Vec<u8>>)>;
 pub struct Subtractor {
     imagestack: imagestack::ImageStack<String>,
     roicol: roi::RoiCollection,
+    /// All ROI collections of the dataset when there is more than one; the
+    /// slot of the `active` collection is a placeholder while it is edited
+    /// as `roicol`.
+    collections: Vec<roi::RoiCollection>,
+    active: usize,
 
     show_subtract: bool,
 
     threshold: f64,
+    auto_threshold: threshold::AutoThreshold,
+    /// Thresholds evaluated in addition to the main one, each written to its
+    /// own area table.
+    sweep: Vec<f64>,
+    #[serde(skip)]
+    sweep_text: String,
+    /// Lags in frames compared in addition to `step`, each written to its
+    /// own area table.
+    lags: Vec<usize>,
+    #[serde(skip)]
+    lags_text: String,
+    /// How the pairs of frames of a run are chosen.
+    pair_source: pairs::PairSource,
+    /// Pairs of `Pairs.csv`, read when the data folder is opened, the pair
+    /// list is chosen or reloaded.
+    #[serde(skip)]
+    pair_list: Option<Result<Vec<(usize, usize)>, String>>,
+    /// Frames per burst, for [`pairs::PairSource::Bursts`].
+    burst_size: usize,
+    /// Frames left out of the measurement, also stored in the data folder.
+    exclusions: exclusion::Exclusions,
+    /// First frame of a range being excluded.
+    #[serde(skip)]
+    exclude_from: Option<usize>,
+
+    direction: roi::Direction,
+
+    algorithm: process::Algorithm,
+
+    normalization: process::Normalization,
+    /// Calibrate the noise once per run instead of for every pair.
+    fixed_noise: bool,
+    /// Normalize each ROI on its own noise.
+    local_noise: bool,
+
+    morphology: process::Morphology,
+
+    calibration: calibration::Calibration,
+    area_outputs: export::AreaOutputs,
+    /// Keep the difference images in the data folder for later runs.
+    diff_cache: bool,
 
     scale: f32,
 
@@ -39,7 +119,10 @@ pub struct Subtractor {
     #[serde(skip)]
     image: Option<imagestack::Image>,
     #[serde(skip)]
-    processing: Option<Promise<()>>,
+    processing: Option<Promise<RunOutput>>,
+    /// Why the last run could not start or write its outputs.
+    #[serde(skip)]
+    process_error: Option<String>,
 
     #[serde(skip)]
     progress_total: usize,
@@ -51,6 +134,96 @@ pub struct Subtractor {
 
     #[serde(skip)]
     cache: Arc<Cache>,
+
+    /// Fixed-scale noise with the frame pairs and the normalization it was
+    /// calibrated on, `None` when it could not be calibrated.
+    #[serde(skip)]
+    noise_cache: Option<(NoiseKey, process::Normalizer, Option<process::FixedNoise>)>,
+    /// Fixed-scale noise being calibrated in the background.
+    #[serde(skip)]
+    noise_pending: Option<(
+        NoiseKey,
+        process::Normalizer,
+        Promise<Option<process::FixedNoise>>,
+    )>,
+    /// Start the run once the calibrations are done.
+    #[serde(skip)]
+    start_requested: bool,
+
+    /// Threshold selected once per run with the settings it was selected on,
+    /// `None` when no pair could be sampled.
+    #[serde(skip)]
+    threshold_cache: Option<(ThresholdKey, Option<f64>)>,
+    /// Threshold being selected once per run in the background.
+    #[serde(skip)]
+    threshold_pending: Option<(ThresholdKey, Promise<Option<f64>>)>,
+    /// Automatic threshold of the previewed frame.
+    #[serde(skip)]
+    auto_value: Option<f64>,
+
+    #[serde(skip)]
+    editor: editor::RoiEditor,
+    #[serde(skip)]
+    ruler: calibration::Ruler,
+
+    #[serde(skip)]
+    detect_source: detect::DetectSource,
+    #[serde(skip)]
+    proposal: Option<detect::Detection>,
+    #[serde(skip)]
+    detecting: Option<Promise<Result<Option<detect::Detection>, String>>>,
+    #[serde(skip)]
+    detect_status: String,
+
+    /// Why Roi.json of the data folder could not be loaded; it is not
+    /// overwritten until the error is dismissed.
+    #[serde(skip)]
+    roi_error: Option<String>,
+
+    /// Name of the active collection before it is edited, restored when
+    /// the new name is rejected.
+    #[serde(skip)]
+    name_before_edit: String,
+    #[serde(skip)]
+    collection_error: Option<String>,
+    /// Why the last imported file was rejected, or the ImageJ export failed.
+    #[serde(skip)]
+    import_error: Option<String>,
+
+    /// ROI problems to confirm before processing.
+    #[serde(skip)]
+    roi_warnings: Option<Vec<String>>,
+
+    #[serde(skip)]
+    summary: Option<summary::GroupSummary>,
+    /// Pairs flagged by the quality control of the last run.
+    #[serde(skip)]
+    qc_flags: Vec<qc::FrameQc>,
+    #[serde(skip)]
+    show_summary: bool,
+}
+
+/// Comma separated lags in frames, e.g. `10, 30`.
+fn parse_lags(text: &str) -> Result<Vec<usize>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.parse::<usize>() {
+            Ok(lag) if lag > 0 => Ok(lag),
+            _ => Err(format!("`{item}` is not a lag of at least 1 frame")),
+        })
+        .collect()
+}
+
+/// Paths of the pairs of frames of a run.
+fn frame_pairs<'a>(
+    images: &'a [std::path::PathBuf],
+    pairs: &[(usize, usize)],
+) -> Vec<(&'a std::path::PathBuf, &'a std::path::PathBuf)> {
+    pairs
+        .iter()
+        .filter_map(|(previous, current)| Some((images.get(*previous)?, images.get(*current)?)))
+        .collect()
 }
 
 fn configure_text_styles(ctx: &egui::Context) {
@@ -121,22 +294,30 @@ impl Subtractor {
                 _ => {
                     let im = match (self.show_subtract, pre) {
                         (true, Some(pre)) => {
+                            let (pre, im_path) = (pre.clone(), im_path.clone());
+                            let normalizer = self.normalizer();
                             let sub =
-                                process::subtract(pre, im_path).expect("fail to to open image");
+                                process::subtract(&pre, &im_path, self.algorithm, &normalizer)
+                                    .expect("fail to to open image");
+                            self.auto_value = self
+                                .run_threshold(&normalizer)
+                                .or_else(|| self.auto_threshold.select(&sub, self.direction));
 
-                            let thresh = (128.0f64 - self.threshold * 12.8f64)
-                                .clamp(0f64, 255f64)
-                                .round() as usize;
+                            // mark the pixels that are counted, after the clean-up
+                            let threshold = self.auto_value.unwrap_or(self.threshold);
+                            let mut mask = roi::binarize(&sub, threshold, self.direction);
+                            for roicol in self.collections() {
+                                roicol.apply_thresholds(&sub, &mut mask, self.direction);
+                            }
+                            self.morphology.apply(&mut mask);
                             let mut im: image::ImageBuffer<image::Rgba<u8>, Vec<u8>> =
                                 image::ImageBuffer::new(sub.width(), sub.height());
-                            let mut rlut: [[u8; 4]; 256] = [[255; 4]; 256];
-                            (0..=255u8).for_each(|val| rlut[val as usize] = [val, val, val, 255]);
-                            (0..=thresh).for_each(|idx| rlut[idx][0] = 255);
-                            im.chunks_exact_mut(4).zip(sub.iter().cloned()).for_each(
-                                |(dst, src)| {
-                                    dst.copy_from_slice(rlut[src as usize].as_slice());
-                                },
-                            );
+                            im.chunks_exact_mut(4)
+                                .zip(sub.iter().zip(mask.iter()))
+                                .for_each(|(dst, (&val, &moving))| {
+                                    let red = if moving > 0 { 255 } else { val };
+                                    dst.copy_from_slice(&[red, val, val, 255]);
+                                });
                             im
                         }
                         (_, _) => image::open(im_path).expect("fail to open image").to_rgba8(),
@@ -148,7 +329,13 @@ impl Subtractor {
 
         if let Some((_, im)) = self.cache.as_ref() {
             let mut im = im.clone();
-            self.roicol.draw_rois(&mut im);
+            match self.proposal.as_ref() {
+                Some(proposal) => proposal.grid.draw_rois(&mut im),
+                None => self
+                    .collections()
+                    .into_iter()
+                    .for_each(|roicol| roicol.draw_rois(&mut im)),
+            }
             let size = [im.width() as usize, im.height() as usize];
             let texture = ui.ctx().load_texture(
                 format!("{}", self.imagestack.pos),
@@ -163,34 +350,628 @@ impl Subtractor {
         }
     }
 
-    fn spawn_a_process(&mut self) -> Promise<()> {
+    fn save_rois(&self) {
+        if self.roi_error.is_some() {
+            return;
+        }
+        if let Some(homedir) = self.imagestack.homedir.as_ref() {
+            let roi_path = Path::new(homedir).join("Roi.json");
+            if let Err(e) = self.roi_file().to_json(roi_path) {
+                eprintln!("fail to write Roi.json: {e}");
+            }
+        }
+    }
+
+    /// Load the excluded frames of the data folder, none when it has no
+    /// exclusions file.
+    fn read_exclusions(&mut self) {
+        self.exclusions = exclusion::Exclusions::default();
+        self.exclude_from = None;
+        let Some(homedir) = self.imagestack.homedir.as_ref() else {
+            return;
+        };
+        let path = Path::new(homedir).join(exclusion::FILE);
+        if path.exists() {
+            match exclusion::Exclusions::read(&path) {
+                Ok(exclusions) => self.exclusions = exclusions,
+                Err(e) => eprintln!("fail to read {}: {e}", exclusion::FILE),
+            }
+        }
+    }
+
+    /// Read the pair list of the data folder.
+    fn read_pair_list(&mut self) {
+        self.pair_list = self.imagestack.homedir.as_ref().and_then(|homedir| {
+            let images = self.imagestack.get_stacks()?;
+            let path = Path::new(homedir).join(pairs::PAIR_LIST);
+            Some(
+                pairs::read_pair_list(path, &images)
+                    .map_err(|e| format!("{}: {e}", pairs::PAIR_LIST)),
+            )
+        });
+    }
+
+    fn save_exclusions(&self) {
+        if let Some(homedir) = self.imagestack.homedir.as_ref() {
+            let path = Path::new(homedir).join(exclusion::FILE);
+            if self.exclusions.is_empty() && !path.exists() {
+                return;
+            }
+            if let Err(e) = self.exclusions.to_json(path) {
+                eprintln!("fail to write {}: {e}", exclusion::FILE);
+            }
+        }
+    }
+
+    /// Buttons to exclude or include again the current frame or a range of
+    /// frames ending at it.
+    fn exclusion_ui(&mut self, ui: &mut egui::Ui) {
+        let pos = self.imagestack.pos;
+        let mut changed = false;
+        if let Some(from) = self.exclude_from {
+            let (first, last) = (from.min(pos), from.max(pos));
+            if ui.button(format!("Exclude {first}-{last}")).clicked() {
+                self.exclusions.exclude(first, last);
+                self.exclude_from = None;
+                changed = true;
+            }
+            if ui.button(format!("Include {first}-{last}")).clicked() {
+                self.exclusions.include(first, last);
+                self.exclude_from = None;
+                changed = true;
+            }
+            if ui.button("Cancel").clicked() {
+                self.exclude_from = None;
+            }
+        } else {
+            if let Some(frame_qc) = self.qc_flags.iter().find(|frame_qc| frame_qc.frame == pos) {
+                ui.colored_label(egui::Color32::from_rgb(255, 165, 0), frame_qc.flag_text());
+            }
+            if self.exclusions.contains(pos) {
+                ui.colored_label(egui::Color32::RED, "Excluded");
+                if ui.button("Include frame").clicked() {
+                    self.exclusions.include(pos, pos);
+                    changed = true;
+                }
+            } else if ui.button("Exclude frame").clicked() {
+                self.exclusions.exclude(pos, pos);
+                changed = true;
+            }
+            if ui
+                .button("Range from here")
+                .on_hover_text("Move to the other end of the range, then exclude or include it")
+                .clicked()
+            {
+                self.exclude_from = Some(pos);
+            }
+        }
+        if changed {
+            self.save_exclusions();
+        }
+    }
+
+    /// Every ROI collection, in order, with the one being edited.
+    fn collections(&self) -> Vec<&roi::RoiCollection> {
+        if self.collections.is_empty() {
+            return vec![&self.roicol];
+        }
+        self.collections
+            .iter()
+            .enumerate()
+            .map(|(idx, roicol)| {
+                if idx == self.active {
+                    &self.roicol
+                } else {
+                    roicol
+                }
+            })
+            .collect()
+    }
+
+    /// Problems of every ROI collection against the size of the current image.
+    fn roi_warnings(&self) -> Vec<String> {
+        let Some(image) = self.image.as_ref() else {
+            return vec![];
+        };
+        let [width, height] = image.size.map(|v| v as u32);
+        let multiple = self.collections.len() > 1;
+        self.collections()
+            .into_iter()
+            .flat_map(|roicol| {
+                roicol.check(width, height).into_iter().map(move |warning| {
+                    if multiple {
+                        format!("{}: {warning}", roicol.name)
+                    } else {
+                        warning.to_string()
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Frame range of a run: the first frame before `start`, the last one
+    /// and the step.
+    fn frame_range(&self) -> (usize, usize, usize) {
+        (
+            std::cmp::min(self.start, self.end).saturating_sub(self.step),
+            std::cmp::max(self.end, self.start),
+            self.step,
+        )
+    }
+
+    /// Pairs `(previous, current)` of frame indices a run subtracts, or why
+    /// the pair list cannot be read.
+    fn pairs(&self) -> Result<Vec<(usize, usize)>, String> {
+        let Some(images) = self.imagestack.get_stacks() else {
+            return Ok(vec![]);
+        };
+        let (start, end, step) = self.frame_range();
+        let burst_size = match self.pair_source {
+            pairs::PairSource::Range => 0,
+            pairs::PairSource::Bursts => self.burst_size,
+            pairs::PairSource::List => {
+                return self
+                    .pair_list
+                    .clone()
+                    .unwrap_or_else(|| Err(format!("{} is not read", pairs::PAIR_LIST)));
+            }
+        };
+        Ok((start..end)
+            .step_by(step.max(1))
+            .map(|idx| (idx, idx + step))
+            .filter(|(previous, current)| {
+                *current < images.len() && pairs::within_burst(*previous, *current, burst_size)
+            })
+            .collect())
+    }
+
+    /// Pairs of a run that are measured, without those touching an excluded
+    /// frame.
+    fn measured_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = self.pairs().unwrap_or_default();
+        pairs.retain(|(previous, current)| !self.exclusions.touches(*previous, *current));
+        pairs
+    }
+
+    /// Normalization of the difference images with the current settings. The
+    /// noise of a fixed-scale run is calibrated on a sample of its pairs and
+    /// kept until the pairs, the method or the ROIs it depends on change.
+    fn normalizer(&mut self) -> process::Normalizer {
+        let mut normalizer = process::Normalizer {
+            method: self.normalization,
+            ..Default::default()
+        };
+        let Some(images) = self.imagestack.get_stacks() else {
+            return normalizer;
+        };
+        let background = self.normalization == process::Normalization::Background;
+        if background || self.local_noise {
+            if let Ok((width, height)) = image::image_dimensions(&images[0]) {
+                if background {
+                    let mask = roi::background_mask(self.collections(), width, height);
+                    normalizer.background = Some(mask.into());
+                }
+                if self.local_noise {
+                    let regions = roi::roi_regions(self.collections(), width, height);
+                    normalizer.regions = Some(regions.into());
+                }
+            }
+        }
+        if !self.fixed_noise {
+            return normalizer;
+        }
+        let key = (Arc::clone(&images), self.measured_pairs());
+        if let Some((cached, estimated, noise)) = &self.noise_cache {
+            if *cached == key && *estimated == normalizer {
+                if let Some((frame, regions)) = noise {
+                    normalizer.fixed = Some(*frame);
+                    normalizer.fixed_regions = Some(regions.as_slice().into());
+                }
+                return normalizer;
+            }
+        }
+        // the noise of each pair is used until the calibration is done
+        let pending = self
+            .noise_pending
+            .as_ref()
+            .is_some_and(|(cached, estimated, _)| *cached == key && *estimated == normalizer);
+        if !pending {
+            let (estimated, pairs) = (normalizer.clone(), key.1.clone());
+            let promise = Promise::spawn_thread("noise", move || {
+                let pairs = frame_pairs(&images, &pairs);
+                process::calibrate_noise(&pairs, &estimated, 16).unwrap_or_else(|e| {
+                    eprintln!("fail to calibrate the noise: {e}");
+                    None
+                })
+            });
+            self.noise_pending = Some((key, normalizer.clone(), promise));
+        }
+        normalizer
+    }
+
+    /// Move the finished calibrations to their caches; returns whether one
+    /// finished.
+    fn poll_calibrations(&mut self) -> bool {
+        let mut finished = false;
+        if let Some((key, estimated, promise)) = self.noise_pending.take() {
+            match promise.try_take() {
+                Ok(noise) => {
+                    self.noise_cache = Some((key, estimated, noise));
+                    finished = true;
+                }
+                Err(promise) => self.noise_pending = Some((key, estimated, promise)),
+            }
+        }
+        if let Some((key, promise)) = self.threshold_pending.take() {
+            match promise.try_take() {
+                Ok(value) => {
+                    self.threshold_cache = Some((key, value));
+                    finished = true;
+                }
+                Err(promise) => self.threshold_pending = Some((key, promise)),
+            }
+        }
+        finished
+    }
+
+    fn calibrating(&self) -> bool {
+        self.noise_pending.is_some() || self.threshold_pending.is_some()
+    }
+
+    /// Threshold selected once for a run on a sample of its pairs, kept until
+    /// the settings it depends on change; `None` unless the automatic
+    /// threshold is set per run, and while it is selected in the background.
+    fn run_threshold(&mut self, normalizer: &process::Normalizer) -> Option<f64> {
+        let auto = self.auto_threshold;
+        if auto.method == threshold::ThresholdMethod::Manual || !auto.per_run {
+            return None;
+        }
+        let images = self.imagestack.get_stacks()?;
+        let key = (
+            (Arc::clone(&images), self.measured_pairs()),
+            self.algorithm,
+            normalizer.clone(),
+            self.direction,
+            auto,
+        );
+        if let Some((cached, value)) = &self.threshold_cache {
+            if *cached == key {
+                return *value;
+            }
+        }
+        // selected with the fixed noise once it is calibrated; the threshold
+        // of each frame is used meanwhile
+        let pending = self
+            .threshold_pending
+            .as_ref()
+            .is_some_and(|(cached, _)| *cached == key);
+        if !pending && self.noise_pending.is_none() {
+            let (images, pairs) = key.0.clone();
+            let (algorithm, normalizer, direction) = (key.1, key.2.clone(), key.3);
+            let promise = Promise::spawn_thread("threshold", move || {
+                let pairs = frame_pairs(&images, &pairs);
+                let stride = (pairs.len() / 16).max(1);
+                let thresholds: Vec<f64> = pairs
+                    .par_iter()
+                    .step_by(stride)
+                    .filter_map(|(im1, im2)| {
+                        let subimg = process::subtract(im1, im2, algorithm, &normalizer).ok()?;
+                        auto.select(&subimg, direction)
+                    })
+                    .collect();
+                threshold::AutoThreshold::calibrate(thresholds)
+            });
+            self.threshold_pending = Some((key, promise));
+        }
+        None
+    }
+
+    fn roi_file(&self) -> schema::RoiFile {
+        schema::RoiFile::new(self.collections().into_iter().cloned().collect())
+    }
+
+    /// Load Roi.json from the data folder, keeping the current ROIs when it
+    /// is missing or invalid.
+    fn read_roi_file(&mut self) {
+        self.roi_error = None;
+        let Some(homedir) = self.imagestack.homedir.as_ref() else {
+            return;
+        };
+        let roi_path = Path::new(homedir).join("Roi.json");
+        if !roi_path.exists() {
+            return;
+        }
+        match schema::RoiFile::read(&roi_path) {
+            Ok(file) => self.load_roi_file(file),
+            Err(e) => self.roi_error = Some(e.to_string()),
+        }
+    }
+
+    fn load_roi_file(&mut self, file: schema::RoiFile) {
+        let mut collections = file.into_collections();
+        self.active = 0;
+        self.roicol = std::mem::take(&mut collections[0]);
+        if collections.len() == 1 {
+            collections.clear();
+        }
+        self.collections = collections;
+        self.collections
+            .iter_mut()
+            .for_each(|roicol| roicol.update_rois());
+        self.roicol.update_rois();
+    }
+
+    fn select_collection(&mut self, idx: usize) {
+        if idx == self.active || idx >= self.collections.len() {
+            return;
+        }
+        self.collections[self.active] = std::mem::take(&mut self.roicol);
+        self.active = idx;
+        self.roicol = std::mem::take(&mut self.collections[idx]);
+        self.editor.cancel_picking();
+        self.proposal = None;
+    }
+
+    /// Add a collection with the grid of the current one, to be moved onto
+    /// the next plate.
+    fn add_collection(&mut self) {
+        if self.collections.is_empty() {
+            if self.roicol.name.is_empty() {
+                self.roicol.name = "Plate 1".to_owned();
+            }
+            self.collections.push(roi::RoiCollection::default());
+        }
+        let idx = self.collections.len();
+        let mut roicol = self.roicol.clone();
+        roicol.name = (idx + 1..)
+            .map(|n| format!("Plate {n}"))
+            .find(|name| self.name_error(idx, name).is_none())
+            .expect("the names are finite");
+        roicol.plate_map.clear();
+        roicol.grouping.clear();
+        self.collections.push(roicol);
+        self.select_collection(self.collections.len() - 1);
+    }
+
+    /// Why `name` cannot name the collection `idx`: it is empty, or its
+    /// outputs would overwrite those of another collection.
+    fn name_error(&self, idx: usize, name: &str) -> Option<String> {
+        let stem = |name: &str| roi::file_stem(name).to_lowercase();
+        if name.trim().is_empty() {
+            return Some("The name of a collection cannot be empty".to_owned());
+        }
+        self.collections()
+            .iter()
+            .enumerate()
+            .any(|(k, roicol)| k != idx && stem(&roicol.name) == stem(name))
+            .then(|| format!("Another collection is already named {:?}", name.trim()))
+    }
+
+    fn remove_collection(&mut self) {
+        if self.collections.is_empty() {
+            return;
+        }
+        self.collections.remove(self.active);
+        self.active = self.active.min(self.collections.len() - 1);
+        self.roicol = std::mem::take(&mut self.collections[self.active]);
+        if self.collections.len() == 1 {
+            self.collections.clear();
+            self.active = 0;
+        }
+    }
+
+    /// Detect the wells in a background thread, the result is picked up by
+    /// [`Self::poll_detection`].
+    fn detect_wells(&mut self) {
+        let source = self.detect_source;
+        let current = self.imagestack.get_current_images(self.step).1.cloned();
+        let stacks = self.imagestack.get_stacks();
+        self.proposal = None;
+        self.detect_status = "Detecting…".to_owned();
+        self.detecting = Some(Promise::spawn_thread("detecting", move || {
+            let image = match source {
+                detect::DetectSource::CurrentFrame => current.map(process::imread_as_gray),
+                detect::DetectSource::MeanProjection => {
+                    stacks.map(|stacks| detect::mean_projection(&stacks, 32))
+                }
+            };
+            match image {
+                None => Err("No image in stack".to_owned()),
+                Some(Err(e)) => Err(format!("fail to open image: {e}")),
+                Some(Ok(im)) => Ok(detect::detect_grid(&im)),
+            }
+        }));
+    }
+
+    /// Take the result of a finished detection; returns whether it finished.
+    fn poll_detection(&mut self) -> bool {
+        let Some(promise) = self.detecting.take() else {
+            return false;
+        };
+        let result = match promise.try_take() {
+            Ok(result) => result,
+            Err(promise) => {
+                self.detecting = Some(promise);
+                return false;
+            }
+        };
+        self.detect_status = match result {
+            Err(e) => e,
+            Ok(None) => "No well grid found".to_owned(),
+            Ok(Some(detection)) => {
+                let status = format!(
+                    "Found {} wells: {} rows x {} cols",
+                    detection.wells, detection.grid.nrow, detection.grid.ncol
+                );
+                self.proposal = Some(detection);
+                status
+            }
+        };
+        true
+    }
+
+    fn start_process(&mut self) {
+        self.process_error = None;
+        let normalizer = self.normalizer();
+        let run_threshold = self.run_threshold(&normalizer);
+        // started again once the calibrations are done
+        self.start_requested = self.calibrating();
+        if self.start_requested {
+            return;
+        }
+        match self.spawn_a_process(normalizer, run_threshold) {
+            Ok(promise) => self.processing = Some(promise),
+            Err(e) => self.process_error = Some(format!("fail to start the process: {e}")),
+        }
+    }
+
+    fn spawn_a_process(
+        &mut self,
+        normalizer: process::Normalizer,
+        run_threshold: Option<f64>,
+    ) -> Result<Promise<RunOutput>, Box<dyn std::error::Error>> {
+        let frame_pairs = self
+            .pairs()
+            .expect("the pair list is checked before processing");
         let homedir = self
             .imagestack
             .homedir
             .as_ref()
             .expect("homedir should be not None while call this function");
-        let mut roicol = self.roicol.clone();
-        roicol.update_rois();
+        let mut collections: Vec<roi::RoiCollection> =
+            self.collections().into_iter().cloned().collect();
+        collections
+            .iter_mut()
+            .for_each(|roicol| roicol.update_rois());
+
+        // every collection gets its own set of outputs, suffixed by its name
+        let multiple = collections.len() > 1;
+        if multiple {
+            if let Some(e) =
+                (0..collections.len()).find_map(|idx| self.name_error(idx, &collections[idx].name))
+            {
+                return Err(e.into());
+            }
+        }
 
         let roi_path = Path::new(homedir).join("Roi.json");
-        roicol.to_json(roi_path).expect("fail to write Roi.json");
+        self.roi_file().to_json(roi_path)?;
 
-        let csv_path = Path::new(homedir).join("Area.csv");
-        let mut writer = csv::Writer::from_path(csv_path).expect("fail to create Area.csv");
-        writer
-            .write_record(&csv::StringRecord::from(vec!["Area"; roicol.len()]))
-            .expect("fail to write csv header");
+        let output = |stem: &str, roicol: &roi::RoiCollection| {
+            if multiple {
+                Path::new(homedir).join(format!("{stem}_{}.csv", roi::file_stem(&roicol.name)))
+            } else {
+                Path::new(homedir).join(format!("{stem}.csv"))
+            }
+        };
+        let mut writers = collections
+            .iter()
+            .map(|roicol| {
+                let mut writer = csv::Writer::from_path(output("Area", roicol))?;
+                writer.write_record(&csv::StringRecord::from(roicol.labels()))?;
+                Ok(writer)
+            })
+            .collect::<Result<Vec<_>, csv::Error>>()?;
+
+        // metadata columns only make sense once a plate map is imported
+        let long_paths: Vec<_> = collections
+            .iter()
+            .map(|roicol| (!roicol.plate_map.is_empty()).then(|| output("AreaLong", roicol)))
+            .collect();
+        let summary_paths: Vec<_> = collections
+            .iter()
+            .map(|roicol| output("GroupSummary", roicol))
+            .collect();
+        let fraction_paths: Vec<_> = collections
+            .iter()
+            .map(|roicol| {
+                self.area_outputs
+                    .fraction
+                    .then(|| output("AreaFraction", roicol))
+            })
+            .collect();
+        let blob_paths: Vec<_> = collections
+            .iter()
+            .map(|roicol| self.area_outputs.blobs.then(|| output("Blobs", roicol)))
+            .collect();
+        let calibration = self.calibration;
+        let um2_paths: Vec<_> = collections
+            .iter()
+            .map(|roicol| {
+                (self.area_outputs.um2 && calibration.um_per_px.is_some())
+                    .then(|| output("AreaUm2", roicol))
+            })
+            .collect();
 
         let threshold = self.threshold;
-        let _start = std::cmp::min(self.start, self.end).saturating_sub(self.step);
-        let _end = std::cmp::max(self.end, self.start);
-        let _step = self.step;
+        let auto_threshold = self.auto_threshold;
+        let threshold_path = (auto_threshold.method != threshold::ThresholdMethod::Manual)
+            .then(|| Path::new(homedir).join("Threshold.csv"));
+        let direction = self.direction;
+        let sweep = self.sweep.clone();
+        let sweep_paths: Vec<Vec<_>> = collections
+            .iter()
+            .map(|roicol| {
+                sweep
+                    .iter()
+                    .map(|t| output(&format!("Area_t{}", threshold::label(*t)), roicol))
+                    .collect()
+            })
+            .collect();
+        let morphology = self.morphology;
+        let algorithm = self.algorithm;
+        let (_start, _end, _step) = self.frame_range();
+
+        export::Parameters {
+            algorithm,
+            direction,
+            normalization: normalizer.method,
+            fixed_noise: normalizer.fixed,
+            local_noise: normalizer.regions.is_some(),
+            threshold,
+            auto_threshold,
+            run_threshold,
+            sweep: sweep.clone(),
+            lags: self.lags.clone(),
+            excluded: self.exclusions.ranges.clone(),
+            pair_source: self.pair_source,
+            burst_size: self.burst_size,
+            morphology,
+            start: _start,
+            end: _end,
+            step: _step,
+            calibration,
+        }
+        .to_json(Path::new(homedir).join("Parameters.json"))?;
 
         let images = self
             .imagestack
             .get_stacks()
             .expect("the stacks are guarantee non-empty");
 
+        // pixels of the ROIs that are actually measured, after clipping
+        let (width, height) = image::image_dimensions(&images[0])?;
+        for roicol in &collections {
+            export::write_roi_areas(output("RoiArea", roicol), roicol, width, height)?;
+        }
+        if self.pair_source != pairs::PairSource::Range {
+            export::write_frame_pairs(
+                Path::new(homedir).join("FramePairs.csv"),
+                &images,
+                &frame_pairs,
+            )?;
+        }
+
+        let diff_cache = self
+            .diff_cache
+            .then(|| diffcache::DiffCache::new(Path::new(homedir).join(diffcache::DIR)))
+            .and_then(|cache| {
+                cache
+                    .map_err(|e| eprintln!("fail to create the cache: {e}"))
+                    .ok()
+            });
+        let cache_params = format!("{algorithm:?}|{}", normalizer.cache_key());
+
         let n_thread = num_cpus::get().saturating_sub(1).max(1);
 
         let pool = rayon::ThreadPoolBuilder::new()
@@ -198,42 +979,298 @@ impl Subtractor {
             .build()
             .expect("Fail to build rayon threadpool");
 
-        self.progress_total = (_end - _start) / _step;
+        // every lag compares the same first frames, the first lag is the pair
+        // itself, `step` apart unless the pairs are listed
+        let lags: Vec<usize> = std::iter::once(_step)
+            .chain(self.lags.iter().copied().filter(|lag| *lag != _step))
+            .collect();
+        let burst_size = match self.pair_source {
+            pairs::PairSource::Bursts => self.burst_size,
+            _ => 0,
+        };
+        let lag_paths: Vec<Vec<_>> = collections
+            .iter()
+            .map(|roicol| {
+                lags[1..]
+                    .iter()
+                    .map(|lag| output(&format!("Area_lag{lag}"), roicol))
+                    .collect()
+            })
+            .collect();
+        // (lag, position of the pair, previous frame, current frame)
+        let work: Vec<(usize, usize, usize, usize)> = frame_pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(pos, &(previous, current))| {
+                lags.iter().enumerate().map(move |(k, lag)| {
+                    let current = if k == 0 { current } else { previous + lag };
+                    (k, pos, previous, current)
+                })
+            })
+            .filter(|(k, _, previous, current)| {
+                *k == 0
+                    || (*current < images.len()
+                        && pairs::within_burst(*previous, *current, burst_size))
+            })
+            .collect();
+
+        self.progress_total = work.len();
+        let exclusions = self.exclusions.clone();
+        let qc_path = self
+            .area_outputs
+            .qc
+            .then(|| Path::new(homedir).join("QC.csv"));
         self.progress_count.store(0, Ordering::SeqCst);
         let count = Arc::clone(&self.progress_count);
 
-        poll_promise::Promise::spawn_thread("processing", move || {
-            let res_sort = pool.install(|| {
-                let mut res_sort: Vec<(usize, Vec<u32>)> = (_start.._end)
-                    .into_par_iter()
-                    .step_by(_step)
-                    .enumerate()
-                    // .with_min_len(chunksize)
-                    .filter_map(|(pos, idx)| {
-                        if let (Some(im1), Some(im2)) = (&images.get(idx), &images.get(idx + _step))
-                        {
-                            let subimg =
-                                process::subtract(im1, im2).expect("failed to subtract the image");
-
-                            let res = roicol
-                                .measure_all(&subimg, threshold)
-                                .expect("fail to measure Roi");
+        Ok(poll_promise::Promise::spawn_thread(
+            "processing",
+            move || {
+                // threshold and measurements of the pair of frames `previous`
+                // and `current`; blobs and the sweep only for the first lag
+                let measure_pair = |frames: &process::FrameSet<'_>,
+                                    previous: usize,
+                                    current: usize,
+                                    primary: bool| {
+                    let (im1, im2) = (&images[previous], &images[current]);
+                    let subtract = || {
+                        Ok(process::subtract_gray(
+                            frames.get(previous),
+                            frames.get(current),
+                            algorithm,
+                            &normalizer,
+                        ))
+                    };
+                    let subimg = match &diff_cache {
+                        Some(diff_cache) => diff_cache.fetch(im1, im2, &cache_params, subtract),
+                        None => subtract(),
+                    }
+                    .expect("failed to subtract the image");
+
+                    let threshold = run_threshold
+                        .or_else(|| auto_threshold.select(&subimg, direction))
+                        .unwrap_or(threshold);
+                    let binarize = |threshold| {
+                        let mut mask = roi::binarize(&subimg, threshold, direction);
+                        for roicol in &collections {
+                            roicol.apply_thresholds(&subimg, &mut mask, direction);
+                        }
+                        morphology.apply(&mut mask);
+                        mask
+                    };
+                    let mask = binarize(threshold);
+                    // the same difference image at every threshold of the sweep
+                    let sweep_masks: Vec<_> = if primary {
+                        sweep.iter().map(|t| binarize(*t)).collect()
+                    } else {
+                        vec![]
+                    };
+                    let res: Vec<Measurement> = collections
+                        .iter()
+                        .enumerate()
+                        .map(|(k, roicol)| {
+                            let area = roicol.measure_mask(&mask).expect("fail to measure Roi");
+                            let stats = if primary && blob_paths[k].is_some() {
+                                blobs::analyze_all(roicol, &mask)
+                            } else {
+                                vec![]
+                            };
+                            let sweep_areas = sweep_masks
+                                .iter()
+                                .map(|mask| roicol.measure_mask(mask).expect("fail to measure Roi"))
+                                .collect();
+                            (area, stats, sweep_areas)
+                        })
+                        .collect();
+                    let frame_qc = (primary && qc_path.is_some()).then(|| {
+                        let mut frame_qc = qc::FrameQc::measure(
+                            previous,
+                            current,
+                            frames.get(previous),
+                            frames.get(current),
+                        );
+                        let areas = res.iter().flat_map(|(area, _, _)| area);
+                        let active = areas.clone().filter(|area| **area > 0).count();
+                        frame_qc.active_fraction = active as f64 / areas.count().max(1) as f64;
+                        frame_qc
+                    });
+                    count.fetch_add(1, Ordering::SeqCst);
+                    (threshold, res, frame_qc)
+                };
+
+                let res_sort = pool.install(|| {
+                    let mut res_sort: Vec<(usize, usize, usize, PairResult)> = vec![];
+                    let measure = |frames: &process::FrameSet<'_>,
+                                   &(k, pos, previous, current): &(usize, usize, usize, usize)| {
+                        // pairs touching an excluded frame are not measured
+                        let res = if exclusions.touches(previous, current) {
                             count.fetch_add(1, Ordering::SeqCst);
-                            Some((pos, res))
-                        } else {
                             None
+                        } else {
+                            Some(measure_pair(frames, previous, current, k == 0))
+                        };
+                        (k, pos, current, res)
+                    };
+                    if lags.len() == 1 {
+                        // nothing to share, each pair reads its frames and drops them
+                        res_sort.par_extend(work.par_iter().map(|pair| {
+                            let frames =
+                                process::FrameSet::new(&images, [pair.2, pair.3], algorithm);
+                            measure(&frames, pair)
+                        }));
+                    } else {
+                        // the frames of a chunk of pairs are read once for all the
+                        // lags, and as many at once as the memory budget allows
+                        let max_frames = (process::FRAME_BUDGET
+                            / (width as usize * height as usize).max(1))
+                        .max(2);
+                        let chunks = process::frame_chunks(
+                            &work,
+                            |(_, _, previous, current)| [*previous, *current],
+                            max_frames,
+                        );
+                        for chunk in chunks {
+                            let frames = process::FrameSet::new(
+                                &images,
+                                chunk
+                                    .iter()
+                                    .filter(|(_, _, previous, current)| {
+                                        !exclusions.touches(*previous, *current)
+                                    })
+                                    .flat_map(|(_, _, previous, current)| [*previous, *current]),
+                                algorithm,
+                            );
+                            res_sort
+                                .par_extend(chunk.par_iter().map(|pair| measure(&frames, pair)));
                         }
-                    })
+                    }
+                    res_sort.par_sort_unstable_by_key(|(k, pos, _, _)| (*k, *pos));
+                    res_sort
+                });
+                let (res_sort, lagged): (Vec<_>, Vec<_>) =
+                    res_sort.into_iter().partition(|(k, _, _, _)| *k == 0);
+                // labelled by the current frame of each pair
+                let res_sort: Vec<(usize, PairResult)> = res_sort
+                    .into_iter()
+                    .map(|(_, _, frame, res)| (frame, res))
                     .collect();
-                res_sort.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
-                res_sort
-            });
 
-            res_sort.into_iter().for_each(|(_, area)| {
-                writer.serialize(area).expect("");
-            });
-            writer.flush().expect("fail to flush the writer");
-        })
+                // the outputs are written once every pair is measured
+                let mut write_outputs = || -> Result<_, Box<dyn std::error::Error>> {
+                    if let Some(threshold_path) = &threshold_path {
+                        let thresholds: Vec<(usize, Option<f64>)> = res_sort
+                            .iter()
+                            .map(|(frame, res)| {
+                                (*frame, res.as_ref().map(|(threshold, _, _)| *threshold))
+                            })
+                            .collect();
+                        export::write_thresholds(threshold_path, &thresholds)?;
+                    }
+
+                    let mut qcs: Vec<qc::FrameQc> = res_sort
+                        .iter()
+                        .filter_map(|(_, res)| res.as_ref()?.2.clone())
+                        .collect();
+                    if let Some(qc_path) = &qc_path {
+                        qc::FrameQc::flag(&mut qcs, &images);
+                        qc::write_csv(qc_path, &frame_pairs, &qcs)?;
+                    }
+                    qcs.retain(|frame_qc| !frame_qc.flags.is_empty());
+
+                    let mut summaries = vec![];
+                    for (k, roicol) in collections.iter().enumerate() {
+                        let writer = &mut writers[k];
+                        let n_roi = roicol.measured().count();
+                        for (_, res) in &res_sort {
+                            match res {
+                                Some((_, res, _)) => writer.serialize(&res[k].0)?,
+                                None => writer.write_record(vec![export::NA; n_roi])?,
+                            }
+                        }
+                        writer.flush()?;
+
+                        let frames: Vec<(usize, Option<Vec<u32>>)> = res_sort
+                            .iter()
+                            .map(|(frame, res)| {
+                                (*frame, res.as_ref().map(|(_, res, _)| res[k].0.clone()))
+                            })
+                            .collect();
+                        if let Some(blob_path) = &blob_paths[k] {
+                            let stats: Vec<(usize, Option<Vec<blobs::BlobStats>>)> = res_sort
+                                .iter()
+                                .map(|(frame, res)| {
+                                    (*frame, res.as_ref().map(|(_, res, _)| res[k].1.clone()))
+                                })
+                                .collect();
+                            export::write_blobs(blob_path, roicol, &stats)?;
+                        }
+                        for (j, lag_path) in lag_paths[k].iter().enumerate() {
+                            // one row per pair of Area.csv, NA when the lagged
+                            // pair is past the last image, spans a burst or is
+                            // excluded
+                            let frames: Vec<(usize, Option<Vec<u32>>)> = res_sort
+                                .iter()
+                                .enumerate()
+                                .map(|(pos, (frame, _))| {
+                                    let area = lagged
+                                        .binary_search_by_key(&(j + 1, pos), |(lag, pos, _, _)| {
+                                            (*lag, *pos)
+                                        })
+                                        .ok()
+                                        .and_then(|i| lagged[i].3.as_ref())
+                                        .map(|(_, res, _)| res[k].0.clone());
+                                    (*frame, area)
+                                })
+                                .collect();
+                            export::write_area_table(lag_path, roicol, &frames, |_, area| {
+                                Some(area as f64)
+                            })?;
+                        }
+                        for (j, sweep_path) in sweep_paths[k].iter().enumerate() {
+                            let frames: Vec<(usize, Option<Vec<u32>>)> = res_sort
+                                .iter()
+                                .map(|(frame, res)| {
+                                    (*frame, res.as_ref().map(|(_, res, _)| res[k].2[j].clone()))
+                                })
+                                .collect();
+                            export::write_area_table(sweep_path, roicol, &frames, |_, area| {
+                                Some(area as f64)
+                            })?;
+                        }
+                        if let Some(long_path) = &long_paths[k] {
+                            export::write_area_long(long_path, roicol, &frames)?;
+                        }
+                        if let Some(fraction_path) = &fraction_paths[k] {
+                            // relative to the pixels that are measured, after clipping;
+                            // ROIs entirely outside the image have no fraction
+                            let effective: Vec<u32> = roicol
+                                .measured()
+                                .map(|roi| roi.pixel_area(width, height))
+                                .collect();
+                            export::write_area_table(fraction_path, roicol, &frames, |k, area| {
+                                (effective[k] > 0).then(|| area as f64 / effective[k] as f64)
+                            })?;
+                        }
+                        if let Some(um2_path) = &um2_paths[k] {
+                            export::write_area_table(um2_path, roicol, &frames, |_, area| {
+                                calibration.area_um2(area)
+                            })?;
+                        }
+                        if let Some(summary) = summary::GroupSummary::new(roicol, &frames) {
+                            summary.to_csv(&summary_paths[k])?;
+                            summaries.push((roicol.name.clone(), summary));
+                        }
+                    }
+                    let summary = if multiple {
+                        summary::GroupSummary::merge(summaries)
+                    } else {
+                        summaries.pop().map(|(_, summary)| summary)
+                    };
+                    Ok((summary, qcs))
+                };
+                write_outputs().map_err(|e| format!("fail to write the outputs: {e}"))
+            },
+        ))
     }
 }
 
@@ -274,7 +1311,36 @@ impl eframe::App for Subtractor {
                 if (self.scale - 1.0).abs() > 0.01 && ui.button("Reset").clicked() {
                     self.scale = 1.0;
                 }
+                ui.separator();
+                ui.label("Edit ROI");
+                ui.add(toggle::toggle(&mut self.editor.enabled));
+                ui.separator();
+                self.exclusion_ui(ui);
+            });
+            // flagged frames under the excluded ones
+            let flagged = self.qc_flags.iter().map(|frame_qc| {
+                (
+                    frame_qc.frame,
+                    frame_qc.frame,
+                    egui::Color32::from_rgb(255, 165, 0),
+                )
             });
+            let excluded = self
+                .exclusions
+                .ranges
+                .iter()
+                .map(|&(first, last)| (first, last, egui::Color32::RED));
+            let marks: Vec<_> = flagged.chain(excluded).collect();
+            let len = self.imagestack.len();
+            if ui
+                .add(timeline::timeline(&mut self.imagestack.pos, len, &marks))
+                .on_hover_text(
+                    "Excluded frames in red, frames flagged by the quality control in orange",
+                )
+                .changed()
+            {
+                self.show_image(ui);
+            }
         });
 
         TopBottomPanel::bottom("progress_bar").show(ctx, |ui| {
@@ -297,7 +1363,15 @@ impl eframe::App for Subtractor {
                         ui.add(progress_bar);
                     }
                     Some(_) => {
-                        self.processing.take();
+                        if let Some(promise) = self.processing.take() {
+                            match promise.block_and_take() {
+                                Ok((summary, qc_flags)) => {
+                                    (self.summary, self.qc_flags) = (summary, qc_flags);
+                                    self.show_summary = self.summary.is_some();
+                                }
+                                Err(e) => self.process_error = Some(e),
+                            }
+                        }
                     }
                 }
             }
@@ -321,13 +1395,9 @@ impl eframe::App for Subtractor {
                         .pick_folder()
                     {
                         self.imagestack.set_homedir(path.display().to_string());
-                        match std::fs::File::open(path.join("Roi.json")){
-                            Ok(fs) => {
-                                let rdr = std::io::BufReader::new(fs);
-                                self.roicol = serde_json::from_reader(rdr).unwrap_or_default();
-                            }
-                            Err(e) => eprintln!("json was not exists:{e}"),
-                        }
+                        self.read_roi_file();
+                        self.read_exclusions();
+                        self.read_pair_list();
                         self.start = 0;
                         self.end = self.imagestack.max_slice();
                         self.roicol.update_rois();
@@ -345,7 +1415,75 @@ impl eframe::App for Subtractor {
             }
 
             ui.separator();
+            if let Some(error) = self.roi_error.clone() {
+                ui.colored_label(ui.visuals().error_fg_color, "Roi.json could not be loaded:");
+                ui.label(error);
+                ui.horizontal(|ui| {
+                    if ui.button("Reload").clicked() {
+                        self.read_roi_file();
+                        self.roicol.update_rois();
+                        self.cache = Arc::new(None);
+                        self.show_image(ui);
+                    }
+                    if ui.button("Overwrite with current ROIs").clicked() {
+                        self.roi_error = None;
+                        self.save_rois();
+                    }
+                });
+                ui.separator();
+            }
             // roicol collections
+            let mut collection_changed = false;
+            ui.horizontal(|ui| {
+                let names: Vec<String> = self.collections().iter().map(|roicol| roicol.name.clone()).collect();
+                if names.len() > 1 {
+                    let mut active = self.active;
+                    egui::ComboBox::from_id_source("roi_collection")
+                        .selected_text(names[active].clone())
+                        .show_ui(ui, |ui| {
+                            for (idx, name) in names.iter().enumerate() {
+                                ui.selectable_value(&mut active, idx, name);
+                            }
+                        });
+                    if active != self.active {
+                        self.select_collection(active);
+                        collection_changed = true;
+                    }
+                }
+                if ui.button("Add collection").on_hover_text("Another grid, e.g. a second plate in the same frame").clicked() {
+                    self.add_collection();
+                    collection_changed = true;
+                }
+                if !self.collections.is_empty() && ui.button("Remove").clicked() {
+                    self.remove_collection();
+                    collection_changed = true;
+                }
+            });
+            if !self.collections.is_empty() {
+                ui.horizontal(|ui| {
+                    ui.label("Name");
+                    let response = ui.text_edit_singleline(&mut self.roicol.name);
+                    if response.gained_focus() {
+                        self.name_before_edit = self.roicol.name.clone();
+                    }
+                    if response.lost_focus() {
+                        self.collection_error = self.name_error(self.active, &self.roicol.name);
+                        if self.collection_error.is_some() {
+                            self.roicol.name = std::mem::take(&mut self.name_before_edit);
+                        }
+                        collection_changed = true;
+                    }
+                });
+                if let Some(e) = &self.collection_error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+            }
+            if collection_changed {
+                self.roicol.update_rois();
+                self.save_rois();
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
             ui.label("Parameters of Region of Interest(ROI)");
             ui.add_space(6.);
             let roi_labels: [&'static str; 9] = [
@@ -359,11 +1497,14 @@ impl eframe::App for Subtractor {
                 "ROI Height",
                 "Rotate",
             ];
+            // position, interval and rotation are taken from the corner fit
+            let is_affine = self.roicol.affine.is_some();
+            let enabled = [true, true, !is_affine, !is_affine, !is_affine, !is_affine, true, true, !is_affine];
             let rois_widgets: Vec<widgets::DragValue<'_>> = vec![
                 widgets::DragValue::new(&mut self.roicol.ncol).suffix(" cols").clamp_range(0..=100),
                 widgets::DragValue::new(&mut self.roicol.nrow).suffix(" rows").clamp_range(0..=100),
-                widgets::DragValue::new(&mut self.roicol.x).suffix(" px").clamp_range(0..=max_width),
-                widgets::DragValue::new(&mut self.roicol.y).suffix(" px").clamp_range(0..=max_height),
+                widgets::DragValue::new(&mut self.roicol.x).suffix(" px").clamp_range(-(max_width as i64)..=max_width as i64),
+                widgets::DragValue::new(&mut self.roicol.y).suffix(" px").clamp_range(-(max_height as i64)..=max_height as i64),
                 widgets::DragValue::new(&mut self.roicol.xinterval).suffix(" px").clamp_range(0..=max_width),
                 widgets::DragValue::new(&mut self.roicol.yinterval).suffix(" px").clamp_range(0..=max_height),
                 widgets::DragValue::new(&mut self.roicol.width).suffix(" px").clamp_range(0..=max_width),
@@ -371,11 +1512,11 @@ impl eframe::App for Subtractor {
                 widgets::DragValue::new(&mut self.roicol.rotate).suffix(" °").clamp_range((-90.)..=90.),
             ];
 
-            if roi_labels.into_iter().zip(rois_widgets.into_iter()).fold(
+            if roi_labels.into_iter().zip(enabled).zip(rois_widgets).fold(
                 false,
-                |changed, (label, widget)| {
+                |changed, ((label, enabled), widget)| {
                     ui.label(label);
-                    changed | ui.add(widget).changed()
+                    changed | ui.add_enabled(enabled, widget).changed()
                 },
             ) {
                 self.roicol.update_rois();
@@ -383,6 +1524,251 @@ impl eframe::App for Subtractor {
                 ctx.request_repaint();
             }
 
+            ui.add_space(6.);
+            ui.label("Plate template and numbering");
+            let mut numbering_changed = false;
+            egui::ComboBox::from_id_source("plate_template")
+                .selected_text(self.roicol.plate.map(|p| p.to_string()).unwrap_or("Custom".to_owned()))
+                .show_ui(ui, |ui| {
+                    for plate in plate::PlateFormat::ALL {
+                        if ui.selectable_label(self.roicol.plate == Some(plate), plate.to_string()).clicked() {
+                            self.roicol.apply_template(plate);
+                            numbering_changed = true;
+                        }
+                    }
+                });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("numbering_order")
+                    .selected_text(self.roicol.numbering.order.to_string())
+                    .show_ui(ui, |ui| {
+                        for order in [plate::NumberingOrder::RowMajor, plate::NumberingOrder::ColumnMajor] {
+                            numbering_changed |= ui.selectable_value(&mut self.roicol.numbering.order, order, order.to_string()).changed();
+                        }
+                    });
+                numbering_changed |= ui.add(widgets::DragValue::new(&mut self.roicol.numbering.start).prefix("start at ").clamp_range(0..=1)).changed();
+            });
+            numbering_changed |= ui.checkbox(&mut self.roicol.numbering.well_names, "Well names (A1, B1, ...)").changed();
+            ui.horizontal(|ui| {
+                if ui.button("Import plate map").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("csv", &["csv"])
+                        .pick_file()
+                    {
+                        self.import_error = None;
+                        match plate::read_plate_map(&path) {
+                            Ok(map) => {
+                                self.roicol.plate_map = map;
+                                numbering_changed = true;
+                                self.save_rois();
+                            }
+                            Err(e) => {
+                                self.import_error = Some(format!("fail to read plate map {}: {e}", path.display()))
+                            }
+                        }
+                    }
+                }
+                if !self.roicol.plate_map.is_empty() && ui.button("Clear").clicked() {
+                    self.roicol.plate_map.clear();
+                    numbering_changed = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Import ROI groups").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("csv", &["csv"])
+                        .pick_file()
+                    {
+                        self.import_error = None;
+                        match summary::read_grouping(&path, &self.roicol) {
+                            Ok(grouping) => {
+                                self.roicol.grouping = grouping;
+                                numbering_changed = true;
+                                self.save_rois();
+                            }
+                            Err(e) => {
+                                self.import_error = Some(format!("fail to read ROI groups {}: {e}", path.display()))
+                            }
+                        }
+                    }
+                }
+                if !self.roicol.grouping.is_empty() && ui.button("Clear").clicked() {
+                    self.roicol.grouping.clear();
+                    numbering_changed = true;
+                }
+                if self.summary.is_some() {
+                    ui.toggle_value(&mut self.show_summary, "Group means");
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Import ImageJ ROIs").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("ImageJ ROI", &["roi", "zip"])
+                        .pick_file()
+                    {
+                        self.import_error = None;
+                        match imagej::read_rois(&path) {
+                            Ok(rois) => {
+                                self.roicol.custom = rois;
+                                self.roicol.offsets.clear();
+                                self.roicol.excluded.clear();
+                                self.roicol.grouping.clear();
+                                numbering_changed = true;
+                                self.save_rois();
+                            }
+                            Err(e) => {
+                                self.import_error = Some(format!("fail to read ImageJ ROIs {}: {e}", path.display()))
+                            }
+                        }
+                    }
+                }
+                if ui.button("Export ImageJ ROIs").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("ImageJ ROI set", &["zip"])
+                        .set_file_name("RoiSet.zip")
+                        .save_file()
+                    {
+                        self.import_error = imagej::write_rois(&path, &self.roicol.to_free_rois())
+                            .err()
+                            .map(|e| format!("fail to write ImageJ ROIs {}: {e}", path.display()));
+                    }
+                }
+                if !self.roicol.custom.is_empty() && ui.button("Clear").clicked() {
+                    self.roicol.custom.clear();
+                    self.roicol.offsets.clear();
+                    self.roicol.excluded.clear();
+                    self.roicol.grouping.clear();
+                    numbering_changed = true;
+                }
+            });
+            if let Some(e) = &self.import_error {
+                ui.colored_label(egui::Color32::RED, e);
+            }
+            for (idx, group) in self.roicol.groups().iter().enumerate() {
+                let [r, g, b, _] = roi::group_color(idx).0;
+                ui.colored_label(egui::Color32::from_rgb(r, g, b), format!("■ {group}"));
+            }
+            // saved right away, as ctrl-click in the editor does
+            let mut exclusion_changed = false;
+            egui::CollapsingHeader::new("Included ROIs").show(ui, |ui| {
+                egui::ScrollArea::both().max_height(200.).show(ui, |ui| {
+                    egui::Grid::new("roi_list").show(ui, |ui| {
+                        let rois = self.roicol.rois().to_vec();
+                        for row in 0..self.roicol.nrow {
+                            for roi in rois.iter().filter(|roi| roi.row == row).sorted_by_key(|roi| roi.col) {
+                                let mut included = !roi.excluded;
+                                if ui.checkbox(&mut included, &roi.label).changed() {
+                                    self.roicol.set_excluded(roi.row, roi.col, !included);
+                                    numbering_changed = true;
+                                    exclusion_changed = true;
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+            let mut thresholds_changed = false;
+            egui::CollapsingHeader::new("ROI thresholds").show(ui, |ui| {
+                ui.label("Own binarization threshold of single ROIs");
+                egui::ScrollArea::both().id_source("roi_thresholds").max_height(200.).show(ui, |ui| {
+                    egui::Grid::new("roi_threshold_list").show(ui, |ui| {
+                        for roi in self.roicol.rois().to_vec().iter().filter(|roi| !roi.excluded) {
+                            let mut own = roi.threshold.is_some();
+                            let mut threshold = roi.threshold.unwrap_or(self.threshold);
+                            let selected = self.editor.selected == Some((roi.row, roi.col));
+                            if ui.selectable_label(selected, &roi.label).clicked() {
+                                // highlighted in the editor
+                                self.editor.selected = Some((roi.row, roi.col));
+                            }
+                            let mut changed = ui.checkbox(&mut own, "own").changed();
+                            changed |= ui
+                                .add_enabled(own, widgets::DragValue::new(&mut threshold).min_decimals(1).clamp_range(-10.0..=10.0))
+                                .changed();
+                            if changed {
+                                self.roicol.set_threshold(roi.row, roi.col, own.then_some(threshold));
+                                thresholds_changed = true;
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+            if thresholds_changed {
+                self.roicol.update_rois();
+                self.save_rois();
+                self.cache = Arc::new(None);
+            }
+            if numbering_changed || thresholds_changed {
+                self.roicol.update_rois();
+                if exclusion_changed {
+                    self.save_rois();
+                }
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
+
+            ui.add_space(6.);
+            ui.label("Fit grid from corner wells");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.editor.fit_mode, roi::FitMode::Rotation, "Rotation");
+                ui.radio_value(&mut self.editor.fit_mode, roi::FitMode::Affine, "Affine");
+            });
+            if let Some(corner) = self.editor.picking() {
+                ui.label(format!("Click the center of the {corner} well"));
+                if ui.button("Cancel").clicked() {
+                    self.editor.cancel_picking();
+                }
+            } else if ui.button("Pick corner wells").clicked() {
+                self.editor.start_picking();
+            }
+            ui.add_space(6.);
+            ui.label("Detect wells automatically");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("detect_source")
+                    .selected_text(self.detect_source.to_string())
+                    .show_ui(ui, |ui| {
+                        for source in [detect::DetectSource::CurrentFrame, detect::DetectSource::MeanProjection] {
+                            ui.selectable_value(&mut self.detect_source, source, source.to_string());
+                        }
+                    });
+                if self.detecting.is_some() {
+                    ui.spinner();
+                    ui.ctx().request_repaint();
+                } else if self.imagestack.homedir.is_some() && ui.button("Detect").clicked() {
+                    self.detect_wells();
+                }
+            });
+            if self.poll_detection() {
+                self.show_image(ui);
+            }
+            if !self.detect_status.is_empty() {
+                ui.label(&self.detect_status);
+            }
+            if self.proposal.is_some() {
+                ui.horizontal(|ui| {
+                    if ui.button("Accept").clicked() {
+                        if let Some(proposal) = self.proposal.take() {
+                            self.roicol = proposal.grid;
+                            self.save_rois();
+                        }
+                        self.detect_status.clear();
+                        self.show_image(ui);
+                    }
+                    if ui.button("Discard").clicked() {
+                        self.proposal = None;
+                        self.detect_status.clear();
+                        self.show_image(ui);
+                    }
+                });
+            }
+            if is_affine && ui.button("Convert affine grid to rotation").clicked() {
+                self.roicol.clear_affine();
+                self.roicol.update_rois();
+                self.save_rois();
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
+
             if ui
                 .add(widgets::Checkbox::new(
                     &mut self.show_subtract,
@@ -398,7 +1784,8 @@ impl eframe::App for Subtractor {
             let maxslice = self.imagestack.max_slice();
             ui.separator();
             ui.label("Binarized threshold (default: 2.0 x std)");
-            if ui.add(
+            if ui.add_enabled(
+                self.auto_threshold.method == threshold::ThresholdMethod::Manual,
                 widgets::DragValue::new(&mut self.threshold)
                     .min_decimals(1)
                     .clamp_range(-10.0..=10.0),
@@ -407,6 +1794,120 @@ impl eframe::App for Subtractor {
                 self.show_image(ui);
                 ctx.request_repaint();
             };
+            let mut auto_changed = false;
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("threshold_method")
+                    .selected_text(self.auto_threshold.method.to_string())
+                    .show_ui(ui, |ui| {
+                        for method in [
+                            threshold::ThresholdMethod::Manual,
+                            threshold::ThresholdMethod::Otsu,
+                            threshold::ThresholdMethod::Percentile,
+                            threshold::ThresholdMethod::Triangle,
+                        ] {
+                            auto_changed |= ui.selectable_value(&mut self.auto_threshold.method, method, method.to_string()).changed();
+                        }
+                    });
+                if self.auto_threshold.method == threshold::ThresholdMethod::Percentile {
+                    auto_changed |= ui
+                        .add(widgets::DragValue::new(&mut self.auto_threshold.percentile).suffix(" %").speed(0.1).clamp_range(50.0..=100.0))
+                        .on_hover_text("Share of the pixels that is noise")
+                        .changed();
+                }
+            });
+            if self.auto_threshold.method != threshold::ThresholdMethod::Manual {
+                ui.horizontal(|ui| {
+                    auto_changed |= ui.checkbox(&mut self.auto_threshold.per_run, "Once per run").changed();
+                    auto_changed |= ui
+                        .add(widgets::DragValue::new(&mut self.auto_threshold.floor).prefix("floor ").suffix(" x std").speed(0.1).clamp_range(0.0..=10.0))
+                        .on_hover_text("Lowest threshold selected, so that frames without motion count little of their noise")
+                        .changed();
+                    if let Some(value) = self.auto_value {
+                        ui.label(format!("auto: {value:.2} x std"));
+                    }
+                });
+            }
+            if auto_changed {
+                self.auto_value = None;
+                self.cache = Arc::new(None);
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
+            ui.horizontal(|ui| {
+                if self.sweep_text.is_empty() && !self.sweep.is_empty() {
+                    self.sweep_text = self.sweep.iter().map(|t| threshold::label(*t)).join(", ");
+                }
+                ui.label("Sweep");
+                let response = ui
+                    .add(egui::TextEdit::singleline(&mut self.sweep_text).hint_text("1.5, 2.0, 2.5").desired_width(120.))
+                    .on_hover_text("Also write the area at each of these thresholds to Area_t<threshold>.csv");
+                match threshold::parse_list(&self.sweep_text) {
+                    Ok(sweep) => {
+                        if response.changed() {
+                            self.sweep = sweep;
+                        }
+                    }
+                    Err(e) => {
+                        ui.colored_label(egui::Color32::RED, e);
+                    }
+                }
+            });
+            let mut algorithm_changed = false;
+            egui::ComboBox::from_label("Algorithm")
+                .selected_text(self.algorithm.to_string())
+                .show_ui(ui, |ui| {
+                    for algorithm in [process::Algorithm::Native, process::Algorithm::ImageJ] {
+                        algorithm_changed |= ui.selectable_value(&mut self.algorithm, algorithm, algorithm.to_string()).changed();
+                    }
+                });
+            let mut direction_changed = false;
+            egui::ComboBox::from_label("Motion")
+                .selected_text(self.direction.to_string())
+                .show_ui(ui, |ui| {
+                    for direction in [roi::Direction::Darker, roi::Direction::Brighter, roi::Direction::Absolute] {
+                        direction_changed |= ui.selectable_value(&mut self.direction, direction, direction.to_string()).changed();
+                    }
+                });
+            let mut normalization_changed = false;
+            ui.add_enabled_ui(self.algorithm == process::Algorithm::Native, |ui| {
+                egui::ComboBox::from_label("Noise")
+                    .selected_text(self.normalization.to_string())
+                    .show_ui(ui, |ui| {
+                        for normalization in [
+                            process::Normalization::Std,
+                            process::Normalization::Mad,
+                            process::Normalization::TrimmedStd,
+                            process::Normalization::Background,
+                        ] {
+                            normalization_changed |= ui.selectable_value(&mut self.normalization, normalization, normalization.to_string()).changed();
+                        }
+                    });
+                normalization_changed |= ui
+                    .checkbox(&mut self.local_noise, "Per-ROI noise")
+                    .on_hover_text("Normalize every ROI on the noise of its own pixels")
+                    .changed();
+                normalization_changed |= ui
+                    .checkbox(&mut self.fixed_noise, "Fixed scale for the run")
+                    .on_hover_text("Calibrate the noise once on a sample of the frame pairs")
+                    .changed();
+            });
+            if algorithm_changed || direction_changed || normalization_changed {
+                self.cache = Arc::new(None);
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
+            ui.label("Clean-up before counting");
+            let mut morphology_changed = false;
+            ui.horizontal(|ui| {
+                morphology_changed |= ui.add(widgets::DragValue::new(&mut self.morphology.open).prefix("open ").suffix(" px").clamp_range(0..=10)).changed();
+                morphology_changed |= ui.add(widgets::DragValue::new(&mut self.morphology.close).prefix("close ").suffix(" px").clamp_range(0..=10)).changed();
+                morphology_changed |= ui.add(widgets::DragValue::new(&mut self.morphology.min_blob_size).prefix("min blob ").suffix(" px").clamp_range(0..=10000)).changed();
+            });
+            if morphology_changed {
+                self.cache = Arc::new(None);
+                self.show_image(ui);
+                ctx.request_repaint();
+            }
             ui.label("Start slice");
             ui.add(
                 widgets::DragValue::new(&mut self.start)
@@ -425,22 +1926,196 @@ impl eframe::App for Subtractor {
                 self.show_image(ui);
                 ctx.request_repaint();
             };
+            ui.horizontal(|ui| {
+                if self.lags_text.is_empty() && !self.lags.is_empty() {
+                    self.lags_text = self.lags.iter().join(", ");
+                }
+                ui.label("Extra lags");
+                let response = ui
+                    .add(egui::TextEdit::singleline(&mut self.lags_text).hint_text("10, 30").desired_width(120.))
+                    .on_hover_text("Also compare frames this many frames apart, written to Area_lag<lag>.csv");
+                match parse_lags(&self.lags_text) {
+                    Ok(lags) => {
+                        if response.changed() {
+                            self.lags = lags;
+                        }
+                    }
+                    Err(e) => {
+                        ui.colored_label(egui::Color32::RED, e);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Pairs");
+                egui::ComboBox::from_id_source("pair_source")
+                    .selected_text(self.pair_source.to_string())
+                    .show_ui(ui, |ui| {
+                        for source in [pairs::PairSource::Range, pairs::PairSource::Bursts, pairs::PairSource::List] {
+                            if ui.selectable_value(&mut self.pair_source, source, source.to_string()).clicked() && source == pairs::PairSource::List {
+                                self.read_pair_list();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text(format!("Subtract every step from start to end, only within bursts of frames, or the pairs of {} in the data folder", pairs::PAIR_LIST));
+                if self.pair_source == pairs::PairSource::Bursts {
+                    ui.add(widgets::DragValue::new(&mut self.burst_size).prefix("burst ").suffix(" frames").clamp_range(1..=maxslice.max(1)));
+                }
+            });
+            if self.pair_source != pairs::PairSource::Range {
+                ui.horizontal(|ui| {
+                    match self.pairs() {
+                        Ok(pairs) => ui.label(format!("{} pairs", pairs.len())),
+                        Err(e) => ui.colored_label(egui::Color32::RED, e),
+                    };
+                    if self.pair_source == pairs::PairSource::List && ui.button("Reload").on_hover_text(format!("Read {} again", pairs::PAIR_LIST)).clicked() {
+                        self.read_pair_list();
+                    }
+                });
+            }
+
+            // calibration and area outputs
+            ui.separator();
+            ui.horizontal(|ui| {
+                let mut calibrated = self.calibration.um_per_px.is_some();
+                if ui.checkbox(&mut calibrated, "µm per pixel").changed() {
+                    self.calibration.um_per_px = calibrated.then_some(1.0);
+                }
+                if let Some(um_per_px) = self.calibration.um_per_px.as_mut() {
+                    ui.add(widgets::DragValue::new(um_per_px).speed(0.01).min_decimals(3).clamp_range(0.0001..=10000.));
+                }
+                if ui.toggle_value(&mut self.ruler.enabled, "Ruler").on_hover_text("Drag a line over a feature of known length").clicked() && self.ruler.enabled {
+                    self.ruler.start();
+                }
+            });
+            if self.ruler.enabled {
+                ui.horizontal(|ui| {
+                    match self.ruler.length_px() {
+                        Some(px) => ui.label(format!("{px:.1} px =")),
+                        None => ui.label("Drag on the image, then enter its length:"),
+                    };
+                    ui.add(widgets::DragValue::new(&mut self.ruler.length_um).suffix(" µm").clamp_range(0.0..=1e6));
+                    if ui.add_enabled(self.ruler.um_per_px().is_some(), widgets::Button::new("Apply")).clicked() {
+                        self.calibration.um_per_px = self.ruler.um_per_px();
+                        self.ruler.enabled = false;
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label("Also write");
+                ui.checkbox(&mut self.area_outputs.fraction, "area fraction");
+                ui.checkbox(&mut self.area_outputs.blobs, "blobs");
+                ui.checkbox(&mut self.area_outputs.qc, "frame QC")
+                    .on_hover_text("Quality-control metrics and flags of every frame in QC.csv");
+                ui.add_enabled(
+                    self.calibration.um_per_px.is_some(),
+                    widgets::Checkbox::new(&mut self.area_outputs.um2, "area in µm²"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.diff_cache, "Cache difference images")
+                    .on_hover_text(format!("Keep the difference images in {} of the data folder, so that runs with another threshold or other ROIs skip the subtraction", diffcache::DIR));
+                if let Some(homedir) = &self.imagestack.homedir {
+                    if self.processing.is_none() && ui.button("Clear cache").clicked() {
+                        if let Err(e) = diffcache::DiffCache::clear(homedir) {
+                            eprintln!("fail to clear the cache: {e}");
+                        }
+                    }
+                }
+            });
+
+            if self.poll_calibrations() {
+                self.cache = Arc::new(None);
+                self.show_image(ui);
+                if self.start_requested {
+                    self.start_process();
+                }
+            }
+            if self.calibrating() {
+                ctx.request_repaint();
+            }
 
             // process block
             ui.separator();
             ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
                 if let Some(homedir) = &self.imagestack.homedir {
+                    if let Some(e) = &self.process_error {
+                        ui.colored_label(egui::Color32::RED, e);
+                    }
                     if self.processing.is_some() {
                         ui.label(format!("Processing the data in: {}", homedir.to_owned()));
+                    } else if self.start_requested {
+                        ui.spinner();
+                        ui.label("Calibrating before the run");
+                    } else if self.roi_error.is_some() {
+                        ui.label("Fix or overwrite Roi.json before processing");
+                    } else if self.pairs().map_or(true, |pairs| pairs.is_empty()) {
+                        ui.label("No pair of frames to process");
                     } else if self.imagestack.max_slice() <= self.step{
                         ui.label("Cannot processing the imagestack if step size is greater than total frame!!");
                     } else if ui.add(widgets::Button::new("Start Process").min_size([128., 48.].into()).rounding(3.6)).clicked() {
-                        let promise = self.spawn_a_process();
-                        self.processing = Some(promise);
+                        let warnings = self.roi_warnings();
+                        if warnings.is_empty() {
+                            self.start_process();
+                        } else {
+                            self.roi_warnings = Some(warnings);
+                        }
                     }
                 }
             });
         });
+        if let Some(warnings) = self.roi_warnings.clone() {
+            let mut proceed = None;
+            egui::Window::new("ROI warnings")
+                .collapsible(false)
+                .default_size([420., 240.])
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "{} problem(s) with the ROIs, highlighted in red:",
+                        warnings.len()
+                    ));
+                    egui::ScrollArea::vertical()
+                        .max_height(240.)
+                        .show(ui, |ui| {
+                            for warning in &warnings {
+                                ui.label(warning);
+                            }
+                        });
+                    ui.horizontal(|ui| {
+                        if ui.button("Process anyway").clicked() {
+                            proceed = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            proceed = Some(false);
+                        }
+                    });
+                });
+            if let Some(proceed) = proceed {
+                self.roi_warnings = None;
+                if proceed {
+                    self.start_process();
+                }
+            }
+        }
+        if let Some(summary) = self.summary.as_ref() {
+            egui::Window::new("Group means")
+                .open(&mut self.show_summary)
+                .default_size([480., 320.])
+                .show(ctx, |ui| {
+                    egui::plot::Plot::new("group_means")
+                        .legend(egui::plot::Legend::default())
+                        .x_axis_formatter(|v, _| format!("frame {v}"))
+                        .show(ui, |plot_ui| {
+                            for (idx, group) in summary.groups.iter().enumerate() {
+                                let [r, g, b, _] = roi::group_color(idx).0;
+                                let line = egui::plot::Line::new(summary.mean_trace(idx))
+                                    .color(egui::Color32::from_rgb(r, g, b))
+                                    .name(group);
+                                plot_ui.line(line);
+                            }
+                        });
+                });
+        }
         CentralPanel::default().show(ctx, |ui| {
             if let Some(im) = &mut self.image {
                 let texture: &egui::TextureHandle = im.texture_id.get_or_insert_with(|| {
@@ -451,28 +2126,56 @@ impl eframe::App for Subtractor {
                         Default::default(),
                     )
                 });
-                let [w, h] = texture.size();
-                let response = ui.add(widgets::ImageButton::new(
-                    texture,
-                    [w as f32 * self.scale, h as f32 * self.scale],
-                ));
+                // the ruler and the editor take the pointer, live play and
+                // zoom still apply
+                let response = if self.ruler.enabled {
+                    let texture = texture.clone();
+                    self.ruler.ui(ui, &texture, self.scale);
+                    None
+                } else if self.editor.enabled {
+                    let texture = texture.clone();
+                    match self.editor.ui(ui, &texture, &mut self.roicol, self.scale) {
+                        editor::EditEvent::None => (),
+                        editor::EditEvent::Changed => {
+                            self.roicol.update_rois();
+                            self.show_image(ui);
+                        }
+                        editor::EditEvent::Finished => {
+                            self.roicol.update_rois();
+                            self.save_rois();
+                            self.show_image(ui);
+                        }
+                    }
+                    None
+                } else {
+                    let [w, h] = texture.size();
+                    Some(ui.add(widgets::ImageButton::new(
+                        texture,
+                        [w as f32 * self.scale, h as f32 * self.scale],
+                    )))
+                };
                 let total = self.imagestack.len();
                 let pos = self.imagestack.pos;
                 if self.is_alive {
                     self.imagestack.pos = (pos + 1) % total;
                 }
-                if response.clicked_by(egui::PointerButton::Primary) {
-                    self.imagestack.pos = (pos + total - self.step) % total;
-                }
-                if response.clicked_by(egui::PointerButton::Secondary) {
-                    self.imagestack.pos = (pos + self.step) % total;
-                }
-
-                if response.clicked_by(egui::PointerButton::Middle) {
-                    self.scale = 1.0;
+                if let Some(response) = &response {
+                    if response.clicked_by(egui::PointerButton::Primary) {
+                        self.imagestack.pos = (pos + total - self.step) % total;
+                    }
+                    if response.clicked_by(egui::PointerButton::Secondary) {
+                        self.imagestack.pos = (pos + self.step) % total;
+                    }
+                    if response.clicked_by(egui::PointerButton::Middle) {
+                        self.scale = 1.0;
+                    }
                 }
 
-                if response.hovered() {
+                let hovered = match &response {
+                    Some(response) => response.hovered(),
+                    None => ui.ui_contains_pointer(),
+                };
+                if hovered {
                     let delta = ui.input(|i| {
                         i.events.iter().find_map(|e| match e {
                             egui::Event::MouseWheel {
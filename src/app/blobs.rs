@@ -0,0 +1,146 @@
+use image::{ImageBuffer, Luma};
+use imageproc::region_labelling::{connected_components, Connectivity};
+
+use super::roi::{Roi, RoiCollection};
+
+type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
+
+/// Connected components of the moving pixels inside one ROI.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlobStats {
+    /// Number of moving pixels, as in `Area.csv`.
+    pub area: u32,
+    /// Number of 8-connected components.
+    pub components: u32,
+    /// Area of the largest component.
+    pub largest: u32,
+    /// Mean position of the moving pixels in image coordinates.
+    pub centroid: Option<(f64, f64)>,
+    /// `[x, y, width, height]` of the moving pixels.
+    pub bbox: Option<[u32; 4]>,
+}
+
+impl BlobStats {
+    pub const COLUMNS: [&'static str; 9] = [
+        "area",
+        "components",
+        "largest_component",
+        "centroid_x",
+        "centroid_y",
+        "bbox_x",
+        "bbox_y",
+        "bbox_width",
+        "bbox_height",
+    ];
+
+    /// Values of [`BlobStats::COLUMNS`], empty where there is no motion.
+    pub fn values(&self) -> [String; 9] {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        let [bx, by, bw, bh] = self
+            .bbox
+            .map(|b| b.map(|v| v.to_string()))
+            .unwrap_or_default();
+        [
+            self.area.to_string(),
+            self.components.to_string(),
+            self.largest.to_string(),
+            opt(self.centroid.map(|(x, _)| format!("{x:.2}"))),
+            opt(self.centroid.map(|(_, y)| format!("{y:.2}"))),
+            bx,
+            by,
+            bw,
+            bh,
+        ]
+    }
+}
+
+/// Blob statistics of the moving pixels (255) of `mask` inside `roi`.
+pub fn analyze(roi: &Roi, mask: &GrayImage) -> BlobStats {
+    let x2 = (roi.x + roi.width).min(mask.width());
+    let y2 = (roi.y + roi.height).min(mask.height());
+    if x2 <= roi.x || y2 <= roi.y {
+        return BlobStats::default();
+    }
+    // moving pixels of the ROI, cropped to its bounding box
+    let crop = GrayImage::from_fn(x2 - roi.x, y2 - roi.y, |x, y| {
+        let (px, py) = (roi.x + x, roi.y + y);
+        let moving = roi.covers(px, py) && mask.get_pixel(px, py)[0] > 0;
+        Luma([if moving { 255 } else { 0 }])
+    });
+    let labels = connected_components(&crop, Connectivity::Eight, Luma([0u8]));
+
+    let mut sizes: Vec<u32> = vec![];
+    let (mut sx, mut sy) = (0f64, 0f64);
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label[0] as usize;
+        if label == 0 {
+            continue;
+        }
+        if sizes.len() < label {
+            sizes.resize(label, 0);
+        }
+        sizes[label - 1] += 1;
+        sx += x as f64;
+        sy += y as f64;
+        (min_x, min_y) = (min_x.min(x), min_y.min(y));
+        (max_x, max_y) = (max_x.max(x), max_y.max(y));
+    }
+    let area: u32 = sizes.iter().sum();
+    if area == 0 {
+        return BlobStats::default();
+    }
+    BlobStats {
+        area,
+        components: sizes.len() as u32,
+        largest: sizes.iter().copied().max().unwrap_or_default(),
+        centroid: Some((
+            roi.x as f64 + sx / area as f64,
+            roi.y as f64 + sy / area as f64,
+        )),
+        bbox: Some([
+            roi.x + min_x,
+            roi.y + min_y,
+            max_x - min_x + 1,
+            max_y - min_y + 1,
+        ]),
+    }
+}
+
+/// [`analyze`] every measured ROI.
+pub fn analyze_all(roicol: &RoiCollection, mask: &GrayImage) -> Vec<BlobStats> {
+    roicol.measured().map(|roi| analyze(roi, mask)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blobs() {
+        let mut roicol = RoiCollection::default();
+        roicol.nrow = 1;
+        roicol.ncol = 2;
+        roicol.x = 0;
+        roicol.y = 0;
+        roicol.xinterval = 10;
+        roicol.width = 10;
+        roicol.height = 10;
+        roicol.update_rois();
+
+        let mut mask = GrayImage::new(20, 10);
+        // a 3x2 worm and a single noise pixel in the first ROI
+        for (x, y) in [(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 2), (8, 8)] {
+            mask.put_pixel(x, y, Luma([255]));
+        }
+        let stats = analyze_all(&roicol, &mask);
+        assert_eq!(stats[0].area, 7);
+        assert_eq!(stats[0].components, 2);
+        assert_eq!(stats[0].largest, 6);
+        assert_eq!(stats[0].bbox, Some([1, 1, 8, 8]));
+        let (cx, cy) = stats[0].centroid.unwrap();
+        assert!((cx - 20. / 7.).abs() < 1e-9 && (cy - 17. / 7.).abs() < 1e-9);
+        assert_eq!(stats[1], BlobStats::default());
+        assert_eq!(stats[1].values()[3], "");
+    }
+}
@@ -0,0 +1,108 @@
+use eframe::egui;
+use egui::{Color32, Pos2, Sense, Stroke};
+use serde::{Deserialize, Serialize};
+
+/// Spatial calibration of the images.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct Calibration {
+    /// Micrometers per pixel, `None` while uncalibrated.
+    pub um_per_px: Option<f64>,
+}
+
+impl Calibration {
+    /// Area of `px` pixels in µm².
+    pub fn area_um2(&self, px: u32) -> Option<f64> {
+        self.um_per_px.map(|scale| px as f64 * scale * scale)
+    }
+}
+
+/// Line drawn on the image across a feature of known length, e.g. the
+/// diameter of a well, to calibrate the pixel size.
+#[derive(Default)]
+pub struct Ruler {
+    pub enabled: bool,
+    /// Known length of the line in µm.
+    pub length_um: f64,
+    line: Option<[Pos2; 2]>,
+}
+
+impl Ruler {
+    pub fn start(&mut self) {
+        self.enabled = true;
+        self.line = None;
+    }
+
+    /// Length of the drawn line in pixels.
+    pub fn length_px(&self) -> Option<f64> {
+        self.line
+            .map(|[a, b]| a.distance(b) as f64)
+            .filter(|length| *length > 0.)
+    }
+
+    /// Micrometers per pixel given by the line and the known length.
+    pub fn um_per_px(&self) -> Option<f64> {
+        self.length_px()
+            .filter(|_| self.length_um > 0.)
+            .map(|px| self.length_um / px)
+    }
+
+    /// Draw the image and let the user drag the line over it.
+    pub fn ui(&mut self, ui: &mut egui::Ui, texture: &egui::TextureHandle, scale: f32) {
+        let [w, h] = texture.size();
+        let response = ui
+            .add(
+                egui::Image::new(texture, [w as f32 * scale, h as f32 * scale])
+                    .sense(Sense::drag()),
+            )
+            .on_hover_cursor(egui::CursorIcon::Crosshair);
+        let rect = response.rect;
+        let to_image = |pos: Pos2| ((pos - rect.min) / scale).to_pos2();
+        let to_screen = |pos: Pos2| rect.min + pos.to_vec2() * scale;
+
+        if response.drag_started() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                self.line = Some([to_image(pos); 2]);
+            }
+        }
+        if let (Some(line), Some(pos)) = (self.line.as_mut(), response.interact_pointer_pos()) {
+            if response.dragged() {
+                line[1] = to_image(pos);
+            }
+        }
+        if let Some([a, b]) = self.line {
+            let painter = ui.painter_at(rect);
+            let color = Color32::from_rgb(0, 200, 255);
+            painter.line_segment([to_screen(a), to_screen(b)], Stroke::new(2.0, color));
+            painter.text(
+                to_screen(b),
+                egui::Align2::LEFT_BOTTOM,
+                format!("{:.1} px", a.distance(b)),
+                egui::FontId::proportional(14.0),
+                color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruler_calibration() {
+        let mut ruler = Ruler::default();
+        ruler.start();
+        assert_eq!(ruler.um_per_px(), None);
+        ruler.line = Some([Pos2::new(10., 10.), Pos2::new(40., 50.)]);
+        ruler.length_um = 100.;
+        assert_eq!(ruler.length_px(), Some(50.));
+
+        let calibration = Calibration {
+            um_per_px: ruler.um_per_px(),
+        };
+        assert_eq!(calibration.um_per_px, Some(2.));
+        assert_eq!(calibration.area_um2(10), Some(40.));
+        assert_eq!(Calibration::default().area_um2(10), None);
+    }
+}
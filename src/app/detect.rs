@@ -0,0 +1,440 @@
+use image::{imageops, GrayImage};
+use imageproc::{edges, filter, gradients};
+use std::path::PathBuf;
+
+use super::process::imread_as_gray;
+use super::roi::{FitMode, RoiCollection};
+
+/// Images are downscaled to this size before looking for wells.
+const MAX_DIM: u32 = 640;
+const MIN_RADIUS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DetectSource {
+    #[default]
+    CurrentFrame,
+    MeanProjection,
+}
+
+impl std::fmt::Display for DetectSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DetectSource::CurrentFrame => write!(f, "Current frame"),
+            DetectSource::MeanProjection => write!(f, "Mean projection"),
+        }
+    }
+}
+
+/// A proposed ROI grid and the number of wells it was fitted to.
+pub struct Detection {
+    pub grid: RoiCollection,
+    pub wells: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Circle {
+    x: f64,
+    y: f64,
+    r: f64,
+}
+
+/// Average up to `n` frames sampled evenly across the stack.
+pub fn mean_projection(paths: &[PathBuf], n: usize) -> Result<GrayImage, image::ImageError> {
+    let step = (paths.len() / n.max(1)).max(1);
+    let mut sum: Vec<u32> = vec![];
+    let mut size = (0, 0);
+    let mut count = 0;
+    for path in paths.iter().step_by(step).take(n.max(1)) {
+        let im = imread_as_gray(path)?;
+        if sum.is_empty() {
+            size = im.dimensions();
+            sum = vec![0; im.len()];
+        }
+        if im.dimensions() != size {
+            continue;
+        }
+        sum.iter_mut()
+            .zip(im.iter())
+            .for_each(|(acc, v)| *acc += *v as u32);
+        count += 1;
+    }
+    let pixels = sum.into_iter().map(|v| (v / count.max(1)) as u8).collect();
+    Ok(GrayImage::from_raw(size.0, size.1, pixels).expect("buffer matches the image size"))
+}
+
+/// Propose a ROI grid from the wells visible in `im`.
+///
+/// Wells are found as circles by gradient-directed Hough voting on the edge
+/// image, then a rotated rectangular lattice is fitted to their centers.
+pub fn detect_grid(im: &GrayImage) -> Option<Detection> {
+    if im.width() == 0 || im.height() == 0 {
+        return None;
+    }
+    let scale = (MAX_DIM as f64 / im.width().max(im.height()) as f64).min(1.0);
+    let small = if scale < 1.0 {
+        imageops::resize(
+            im,
+            (im.width() as f64 * scale).round() as u32,
+            (im.height() as f64 * scale).round() as u32,
+            imageops::FilterType::Triangle,
+        )
+    } else {
+        im.clone()
+    };
+    let small = filter::gaussian_blur_f32(&small, 1.5);
+
+    let circles = find_circles(&small);
+    let lattice = fit_lattice(&circles)?;
+
+    let radius = median(circles.iter().map(|c| c.r).collect())? / scale;
+    let side = (radius * std::f64::consts::SQRT_2).round().max(1.) as u32;
+    let mut grid = RoiCollection::default();
+    grid.nrow = lattice.nrow;
+    grid.ncol = lattice.ncol;
+    grid.width = side;
+    grid.height = side;
+    let corner = |i: u32, j: u32| {
+        let (x, y) = lattice.center(i, j);
+        (x / scale, y / scale)
+    };
+    grid.fit_corners(
+        corner(0, 0),
+        corner(0, lattice.ncol - 1),
+        corner(lattice.nrow - 1, 0),
+        FitMode::Rotation,
+    );
+    grid.update_rois();
+    Some(Detection {
+        grid,
+        wells: lattice.inliers,
+    })
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    Some(values[values.len() / 2])
+}
+
+fn find_circles(im: &GrayImage) -> Vec<Circle> {
+    let (width, height) = (im.width() as usize, im.height() as usize);
+    let gx = gradients::horizontal_sobel(im);
+    let gy = gradients::vertical_sobel(im);
+
+    let mut mags: Vec<f32> = gx
+        .iter()
+        .zip(gy.iter())
+        .map(|(&dx, &dy)| (dx as f32).hypot(dy as f32))
+        .collect();
+    if mags.is_empty() {
+        return vec![];
+    }
+    mags.sort_by(f32::total_cmp);
+    let high = mags[mags.len() * 95 / 100].max(8.0);
+    let edge = edges::canny(im, high * 0.5, high);
+
+    let max_radius = (width.min(height) / 4).max(MIN_RADIUS + 1);
+    let edge_points: Vec<(usize, usize)> = edge
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p.0[0] > 0)
+        .map(|(x, y, _)| (x as usize, y as usize))
+        .collect();
+
+    // every edge pixel votes along its gradient, on both sides because wells
+    // may be darker or brighter than the plate
+    let mut acc = vec![0u32; width * height];
+    for &(x, y) in &edge_points {
+        let dx = gx.get_pixel(x as u32, y as u32).0[0] as f64;
+        let dy = gy.get_pixel(x as u32, y as u32).0[0] as f64;
+        let norm = dx.hypot(dy);
+        if norm == 0. {
+            continue;
+        }
+        let (ux, uy) = (dx / norm, dy / norm);
+        for sign in [-1., 1.] {
+            for r in MIN_RADIUS..=max_radius {
+                let cx = (x as f64 + sign * r as f64 * ux).round();
+                let cy = (y as f64 + sign * r as f64 * uy).round();
+                if cx < 0. || cy < 0. || cx >= width as f64 || cy >= height as f64 {
+                    break;
+                }
+                acc[cy as usize * width + cx as usize] += 1;
+            }
+        }
+    }
+
+    // 3x3 box sum to merge votes scattered by rounding
+    let mut smooth = vec![0u32; width * height];
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            smooth[y * width + x] = (y - 1..=y + 1)
+                .flat_map(|yy| (x - 1..=x + 1).map(move |xx| (xx, yy)))
+                .map(|(xx, yy)| acc[yy * width + xx])
+                .sum();
+        }
+    }
+
+    let vmax = smooth.iter().copied().max().unwrap_or(0);
+    if vmax == 0 {
+        return vec![];
+    }
+    let floor = vmax * 35 / 100;
+    let win = MIN_RADIUS as isize;
+    let mut peaks: Vec<(u32, usize, usize)> = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let v = smooth[y * width + x];
+            if v < floor {
+                continue;
+            }
+            let is_max = (-win..=win).all(|oy| {
+                (-win..=win).all(|ox| {
+                    let (xx, yy) = (x as isize + ox, y as isize + oy);
+                    xx < 0
+                        || yy < 0
+                        || xx >= width as isize
+                        || yy >= height as isize
+                        || smooth[yy as usize * width + xx as usize] <= v
+                })
+            });
+            if is_max {
+                peaks.push((v, x, y));
+            }
+        }
+    }
+    peaks.sort_by_key(|p| std::cmp::Reverse(p.0));
+
+    let mut circles: Vec<Circle> = vec![];
+    for (_, x, y) in peaks {
+        let (x, y) = (x as f64, y as f64);
+        let Some(r) = estimate_radius(&edge_points, x, y, max_radius) else {
+            continue;
+        };
+        if circles
+            .iter()
+            .all(|c| (c.x - x).hypot(c.y - y) > c.r.max(r))
+        {
+            circles.push(Circle { x, y, r });
+        }
+    }
+
+    // drop circles whose radius does not match the bulk of the wells
+    let Some(r) = median(circles.iter().map(|c| c.r).collect()) else {
+        return circles;
+    };
+    circles.retain(|c| (c.r - r).abs() <= r * 0.25);
+    circles
+}
+
+/// Radius with the highest density of edge pixels around (`cx`, `cy`).
+fn estimate_radius(
+    edge_points: &[(usize, usize)],
+    cx: f64,
+    cy: f64,
+    max_radius: usize,
+) -> Option<f64> {
+    let mut hist = vec![0u32; max_radius + 2];
+    for &(x, y) in edge_points {
+        let d = (x as f64 - cx).hypot(y as f64 - cy).round() as usize;
+        if d <= max_radius {
+            hist[d] += 1;
+        }
+    }
+    (MIN_RADIUS..=max_radius)
+        .map(|r| {
+            let count = hist[r - 1] + hist[r] + hist[r + 1];
+            // normalize by the circumference so that large radii are not favored
+            (r, count as f64 / r as f64)
+        })
+        .filter(|(_, density)| *density >= 1.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(r, _)| r as f64)
+}
+
+struct Lattice {
+    rotate: f64,
+    origin: (f64, f64),
+    xinterval: f64,
+    yinterval: f64,
+    nrow: u32,
+    ncol: u32,
+    inliers: usize,
+}
+
+impl Lattice {
+    /// Center of the well at (`row`, `col`) in image coordinates.
+    fn center(&self, row: u32, col: u32) -> (f64, f64) {
+        let (rot_sin, rot_cos) = self.rotate.sin_cos();
+        let u = self.origin.0 + col as f64 * self.xinterval;
+        let v = self.origin.1 + row as f64 * self.yinterval;
+        (u * rot_cos - v * rot_sin, u * rot_sin + v * rot_cos)
+    }
+}
+
+/// Least squares fit of `value = offset + index * slope`.
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_i = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_v = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let var = points.iter().map(|p| (p.0 - mean_i).powi(2)).sum::<f64>();
+    if var == 0. {
+        return None;
+    }
+    let cov = points
+        .iter()
+        .map(|p| (p.0 - mean_i) * (p.1 - mean_v))
+        .sum::<f64>();
+    let slope = cov / var;
+    Some((mean_v - slope * mean_i, slope))
+}
+
+fn fit_lattice(circles: &[Circle]) -> Option<Lattice> {
+    if circles.len() < 4 {
+        return None;
+    }
+    let nearest: Vec<f64> = circles
+        .iter()
+        .enumerate()
+        .filter_map(|(i, a)| {
+            circles
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, b)| (a.x - b.x).hypot(a.y - b.y))
+                .min_by(f64::total_cmp)
+        })
+        .collect();
+    let spacing = median(nearest)?;
+
+    let neighbours: Vec<(f64, f64)> = circles
+        .iter()
+        .flat_map(|a| circles.iter().map(move |b| (b.x - a.x, b.y - a.y)))
+        .filter(|(dx, dy)| {
+            let d = dx.hypot(*dy);
+            d > spacing * 0.75 && d < spacing * 1.5
+        })
+        .collect();
+
+    // the grid axes are 90 degrees apart, fold every direction into [-45, 45)
+    let quarter = std::f64::consts::FRAC_PI_2;
+    let rotate = median(
+        neighbours
+            .iter()
+            .map(|(dx, dy)| (dy.atan2(*dx) + quarter / 2.).rem_euclid(quarter) - quarter / 2.)
+            .collect(),
+    )?;
+    let (rot_sin, rot_cos) = rotate.sin_cos();
+    let unrotate = |x: f64, y: f64| (x * rot_cos + y * rot_sin, -x * rot_sin + y * rot_cos);
+
+    let steps: Vec<(f64, f64)> = neighbours
+        .iter()
+        .map(|(dx, dy)| unrotate(*dx, *dy))
+        .collect();
+    let xinterval = median(
+        steps
+            .iter()
+            .filter(|(u, v)| u.abs() > v.abs())
+            .map(|(u, _)| u.abs())
+            .collect(),
+    )
+    .unwrap_or(spacing);
+    let yinterval = median(
+        steps
+            .iter()
+            .filter(|(u, v)| v.abs() > u.abs())
+            .map(|(_, v)| v.abs())
+            .collect(),
+    )
+    .unwrap_or(spacing);
+
+    let points: Vec<(f64, f64)> = circles.iter().map(|c| unrotate(c.x, c.y)).collect();
+    let umin = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let vmin = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let mut fit = (umin, xinterval, vmin, yinterval);
+    let mut inliers: Vec<(i64, i64, f64, f64)> = vec![];
+
+    for _ in 0..2 {
+        let (ou, su, ov, sv) = fit;
+        inliers = points
+            .iter()
+            .map(|&(u, v)| {
+                let j = ((u - ou) / su).round();
+                let i = ((v - ov) / sv).round();
+                (i as i64, j as i64, u, v)
+            })
+            .filter(|&(i, j, u, v)| {
+                (u - ou - j as f64 * su).abs() < su * 0.3
+                    && (v - ov - i as f64 * sv).abs() < sv * 0.3
+            })
+            .collect();
+        if inliers.len() < 4 {
+            return None;
+        }
+        let cols: Vec<(f64, f64)> = inliers.iter().map(|p| (p.1 as f64, p.2)).collect();
+        let rows: Vec<(f64, f64)> = inliers.iter().map(|p| (p.0 as f64, p.3)).collect();
+        let (ou, su) = linear_fit(&cols).unwrap_or((ou, su));
+        let (ov, sv) = linear_fit(&rows).unwrap_or((ov, sv));
+        fit = (ou, su, ov, sv);
+    }
+
+    let (imin, imax) = inliers.iter().fold((i64::MAX, i64::MIN), |(lo, hi), p| {
+        (lo.min(p.0), hi.max(p.0))
+    });
+    let (jmin, jmax) = inliers.iter().fold((i64::MAX, i64::MIN), |(lo, hi), p| {
+        (lo.min(p.1), hi.max(p.1))
+    });
+    let (ou, su, ov, sv) = fit;
+    Some(Lattice {
+        rotate,
+        origin: (ou + jmin as f64 * su, ov + imin as f64 * sv),
+        xinterval: su,
+        yinterval: sv,
+        nrow: (imax - imin + 1) as u32,
+        ncol: (jmax - jmin + 1) as u32,
+        inliers: inliers.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::drawing::draw_filled_circle_mut;
+
+    #[test]
+    fn test_detect_rotated_plate() {
+        let mut im = GrayImage::from_pixel(900, 640, image::Luma([200]));
+        let (rot_sin, rot_cos) = 3.0f64.to_radians().sin_cos();
+        for (i, j) in itertools::iproduct!(0..4, 0..6) {
+            let u = 120. + j as f64 * 120.;
+            let v = 90. + i as f64 * 125.;
+            let x = u * rot_cos - v * rot_sin;
+            let y = u * rot_sin + v * rot_cos;
+            draw_filled_circle_mut(&mut im, (x as i32, y as i32), 45, image::Luma([60]));
+        }
+
+        let detection = detect_grid(&im).expect("wells should be detected");
+        let grid = detection.grid;
+        assert_eq!((grid.nrow, grid.ncol), (4, 6));
+        assert!((grid.rotate - 3.0).abs() < 0.5, "rotate: {}", grid.rotate);
+        assert!(
+            grid.xinterval.abs_diff(120) <= 3,
+            "xinterval: {}",
+            grid.xinterval
+        );
+        assert!(
+            grid.yinterval.abs_diff(125) <= 3,
+            "yinterval: {}",
+            grid.yinterval
+        );
+        assert!(grid.width.abs_diff(64) <= 6, "width: {}", grid.width);
+    }
+
+    #[test]
+    fn test_detect_empty_image() {
+        assert!(detect_grid(&GrayImage::new(0, 0)).is_none());
+        assert!(detect_grid(&GrayImage::from_pixel(64, 48, image::Luma([128]))).is_none());
+        assert!(find_circles(&GrayImage::new(2, 2)).is_empty());
+    }
+}
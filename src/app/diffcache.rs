@@ -0,0 +1,163 @@
+//! On-disk cache of the normalized difference images, so that a run with a
+//! new threshold or new ROIs only redoes the measurement.
+use image::{ImageBuffer, Luma};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
+
+/// Name of the cache folder inside the data folder.
+pub const DIR: &str = ".soustraire-cache";
+
+/// Version of the difference images in the key: the crate version, and a
+/// counter to bump on any change of the subtraction, filters or LUTs.
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), ".1");
+
+/// Gzip files of difference images, named after the hash of their key.
+///
+/// The key holds the version of the subtraction code, the size and CRC-32 of
+/// both input files and the processing parameters. It is also stored in the file and compared on read, so a
+/// collision of the file names is only a miss.
+pub struct DiffCache {
+    dir: PathBuf,
+}
+
+impl DiffCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Key of the difference of `img1` and `img2` with the given `params`.
+    pub fn key<P: AsRef<Path>>(img1: P, img2: P, params: &str) -> std::io::Result<String> {
+        let hash = |path: P| -> std::io::Result<String> {
+            let contents = std::fs::read(path)?;
+            Ok(format!(
+                "{}-{:08x}",
+                contents.len(),
+                crc32fast::hash(&contents)
+            ))
+        };
+        Ok(format!(
+            "{VERSION}:{}:{}:{params}",
+            hash(img1)?,
+            hash(img2)?
+        ))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let name = format!("{:08x}{:08x}", crc32fast::hash(key.as_bytes()), key.len());
+        self.dir.join(name).with_extension("gz")
+    }
+
+    /// Cached image of `key`, `None` when missing, unreadable or stored
+    /// under another key.
+    pub fn get(&self, key: &str) -> Option<GrayImage> {
+        let file = std::fs::File::open(self.path(key)).ok()?;
+        let mut contents = vec![];
+        flate2::read::GzDecoder::new(file)
+            .read_to_end(&mut contents)
+            .ok()?;
+        let u32_at = |pos: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                contents.get(pos..pos + 4)?.try_into().ok()?,
+            ))
+        };
+        let key_len = u32_at(0)? as usize;
+        if contents.get(4..4 + key_len)? != key.as_bytes() {
+            return None;
+        }
+        let (width, height) = (u32_at(4 + key_len)?, u32_at(8 + key_len)?);
+        GrayImage::from_raw(width, height, contents[12 + key_len..].to_vec())
+    }
+
+    pub fn put(&self, key: &str, image: &GrayImage) -> Result<(), Box<dyn Error>> {
+        let path = self.path(key);
+        // rename into place, so that a reader never sees a partial file
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tmp)?,
+            flate2::Compression::fast(),
+        );
+        encoder.write_all(&(key.len() as u32).to_le_bytes())?;
+        encoder.write_all(key.as_bytes())?;
+        encoder.write_all(&image.width().to_le_bytes())?;
+        encoder.write_all(&image.height().to_le_bytes())?;
+        encoder.write_all(image.as_raw())?;
+        encoder.finish()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Difference of `img1` and `img2` from the cache, or computed by
+    /// `subtract` and stored.
+    pub fn fetch<P, F>(
+        &self,
+        img1: P,
+        img2: P,
+        params: &str,
+        subtract: F,
+    ) -> Result<GrayImage, image::ImageError>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Result<GrayImage, image::ImageError>,
+    {
+        let Ok(key) = Self::key(img1, img2, params) else {
+            return subtract();
+        };
+        if let Some(image) = self.get(&key) {
+            return Ok(image);
+        }
+        let image = subtract()?;
+        if let Err(e) = self.put(&key, &image) {
+            eprintln!("fail to write the difference cache: {e}");
+        }
+        Ok(image)
+    }
+
+    /// Remove the cache folder of a data folder.
+    pub fn clear<P: AsRef<Path>>(homedir: P) -> std::io::Result<()> {
+        let dir = homedir.as_ref().join(DIR);
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_cache() {
+        let dir = std::env::temp_dir().join(format!("soustraire-cache-{}", std::process::id()));
+        let cache = DiffCache::new(&dir).unwrap();
+        let (img1, img2) = (dir.join("1.pgm"), dir.join("2.pgm"));
+        std::fs::write(&img1, b"frame 1").unwrap();
+        std::fs::write(&img2, b"frame 2").unwrap();
+
+        let key = DiffCache::key(&img1, &img2, "Native").unwrap();
+        assert_eq!(cache.get(&key), None);
+        let image = GrayImage::from_fn(3, 2, |x, y| Luma([(x * 10 + y) as u8]));
+        cache.put(&key, &image).unwrap();
+        assert_eq!(cache.get(&key), Some(image));
+
+        // other parameters or another input are misses
+        assert_eq!(
+            cache.get(&DiffCache::key(&img1, &img2, "ImageJ").unwrap()),
+            None
+        );
+        std::fs::write(&img2, b"frame 3").unwrap();
+        assert_eq!(
+            cache.get(&DiffCache::key(&img1, &img2, "Native").unwrap()),
+            None
+        );
+
+        assert!(key.starts_with(VERSION));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,314 @@
+use eframe::egui;
+use egui::{Color32, Pos2, Rect, Sense, Stroke, Vec2};
+
+use super::roi::{FitMode, RoiCollection};
+
+const HANDLE_RADIUS: f32 = 6.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Handle {
+    Grid,
+    Spacing,
+    Size,
+    Rotate,
+    Roi(u32, u32),
+}
+
+struct Drag {
+    handle: Handle,
+    origin: Pos2,
+    start: RoiCollection,
+}
+
+#[derive(PartialEq)]
+pub enum EditEvent {
+    None,
+    Changed,
+    Finished,
+}
+
+/// Mouse interaction on the image canvas to move, stretch and rotate the ROI grid.
+///
+/// * drag on the image: move the whole grid
+/// * drag the square handle: change the X/Y interval
+/// * drag the circle handle: change the ROI width/height
+/// * drag the diamond handle: rotate around the first ROI
+/// * shift + drag on a ROI: move that ROI only
+/// * click on a ROI: select it, then nudge with arrow keys, `Delete` to reset
+/// * ctrl + click on a ROI: exclude it from (or include it back into) the measurement
+///
+/// While picking corners, the clicks record the centers of the top-left,
+/// top-right and bottom-left wells instead, and the grid is fitted to them.
+#[derive(Default)]
+pub struct RoiEditor {
+    pub enabled: bool,
+    pub selected: Option<(u32, u32)>,
+    pub fit_mode: FitMode,
+    drag: Option<Drag>,
+    corners: Option<Vec<(f64, f64)>>,
+}
+
+const CORNER_NAMES: [&str; 3] = ["top-left", "top-right", "bottom-left"];
+
+struct Canvas {
+    rect: Rect,
+    scale: f32,
+}
+
+impl Canvas {
+    fn to_screen(&self, (x, y): (f64, f64)) -> Pos2 {
+        self.rect.min + Vec2::new(x as f32, y as f32) * self.scale
+    }
+    fn to_image(&self, pos: Pos2) -> (f64, f64) {
+        let v = (pos - self.rect.min) / self.scale;
+        (v.x as f64, v.y as f64)
+    }
+}
+
+fn handles(roicol: &RoiCollection, canvas: &Canvas) -> [(Handle, Pos2); 3] {
+    let last = roicol.grid_point(roicol.nrow.saturating_sub(1), roicol.ncol.saturating_sub(1));
+    let (x0, y0) = roicol.grid_point(0, 0);
+    let (xr, yr) = roicol.grid_point(0, roicol.ncol.saturating_sub(1));
+    let size = (x0 + roicol.width as f64, y0 + roicol.height as f64);
+    // the rotate handle sits half a ROI above the top-right ROI
+    let (ux, uy) = roicol.row_direction();
+    let lift = roicol.height as f64 / 2.0;
+    let rotate = (xr - lift * ux, yr - lift * uy);
+    [
+        (Handle::Spacing, canvas.to_screen(last)),
+        (Handle::Size, canvas.to_screen(size)),
+        (Handle::Rotate, canvas.to_screen(rotate)),
+    ]
+}
+
+impl RoiEditor {
+    pub fn start_picking(&mut self) {
+        self.enabled = true;
+        self.drag = None;
+        self.corners = Some(Vec::with_capacity(3));
+    }
+
+    pub fn cancel_picking(&mut self) {
+        self.corners = None;
+    }
+
+    /// Name of the next well to click while picking corners.
+    pub fn picking(&self) -> Option<&'static str> {
+        self.corners
+            .as_ref()
+            .and_then(|corners| CORNER_NAMES.get(corners.len()).copied())
+    }
+
+    fn pick(
+        &mut self,
+        response: &egui::Response,
+        canvas: &Canvas,
+        roicol: &mut RoiCollection,
+    ) -> EditEvent {
+        let Some(corners) = self.corners.as_mut() else {
+            return EditEvent::None;
+        };
+        if response.clicked() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                corners.push(canvas.to_image(pos));
+            }
+        }
+        if let [top_left, top_right, bottom_left] = corners[..] {
+            roicol.fit_corners(top_left, top_right, bottom_left, self.fit_mode);
+            self.corners = None;
+            return EditEvent::Finished;
+        }
+        let painter = response.ctx.layer_painter(response.layer_id);
+        let color = Color32::from_rgb(255, 80, 80);
+        for (i, &point) in corners.iter().enumerate() {
+            let pos = canvas.to_screen(point);
+            painter.circle_stroke(pos, HANDLE_RADIUS, Stroke::new(2.0, color));
+            painter.text(
+                pos + Vec2::new(HANDLE_RADIUS, -HANDLE_RADIUS),
+                egui::Align2::LEFT_BOTTOM,
+                CORNER_NAMES[i],
+                egui::FontId::proportional(14.0),
+                color,
+            );
+        }
+        EditEvent::None
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        texture: &egui::TextureHandle,
+        roicol: &mut RoiCollection,
+        scale: f32,
+    ) -> EditEvent {
+        let [w, h] = texture.size();
+        let response = ui.add(
+            egui::Image::new(texture, [w as f32 * scale, h as f32 * scale])
+                .sense(Sense::click_and_drag()),
+        );
+        let canvas = Canvas {
+            rect: response.rect,
+            scale,
+        };
+        if self.corners.is_some() {
+            let response = response.on_hover_cursor(egui::CursorIcon::Crosshair);
+            return self.pick(&response, &canvas, roicol);
+        }
+        let handles = handles(roicol, &canvas);
+        let mut event = EditEvent::None;
+
+        if response.drag_started() {
+            if let Some(origin) = response.interact_pointer_pos() {
+                let shift = ui.input(|i| i.modifiers.shift);
+                let (px, py) = canvas.to_image(origin);
+                let handle = handles
+                    .iter()
+                    .find(|(_, pos)| pos.distance(origin) <= HANDLE_RADIUS * 1.5)
+                    .map(|(handle, _)| *handle)
+                    .or_else(|| {
+                        roicol
+                            .roi_at(px, py)
+                            .filter(|_| shift)
+                            .map(|(row, col)| Handle::Roi(row, col))
+                    })
+                    .unwrap_or(Handle::Grid);
+                if let Handle::Roi(row, col) = handle {
+                    self.selected = Some((row, col));
+                }
+                self.drag = Some(Drag {
+                    handle,
+                    origin,
+                    start: roicol.clone(),
+                });
+            }
+        }
+
+        if let (Some(drag), Some(pos)) = (self.drag.as_ref(), response.interact_pointer_pos()) {
+            let delta = (pos - drag.origin) / scale;
+            let (dx, dy) = (delta.x as f64, delta.y as f64);
+            let mut moved = drag.start.clone();
+            match drag.handle {
+                Handle::Grid => moved.translate(dx, dy),
+                Handle::Spacing => {
+                    let (px, py) = canvas.to_image(pos);
+                    moved.stretch_to(px, py);
+                }
+                Handle::Size => {
+                    moved.width = (drag.start.width as f64 + dx).round().max(1.) as u32;
+                    moved.height = (drag.start.height as f64 + dy).round().max(1.) as u32;
+                }
+                Handle::Rotate => {
+                    let pivot = canvas.to_screen(drag.start.grid_point(0, 0));
+                    let a0 = (drag.origin - pivot).angle();
+                    let a1 = (pos - pivot).angle();
+                    moved.rotate_by(((a1 - a0) as f64).to_degrees());
+                    moved.rotate = (moved.rotate * 10.).round() / 10.;
+                }
+                Handle::Roi(row, col) => {
+                    let (ox, oy) = drag
+                        .start
+                        .offset(row, col)
+                        .map(|o| (o.dx, o.dy))
+                        .unwrap_or_default();
+                    moved.set_offset(row, col, ox + dx.round() as i32, oy + dy.round() as i32);
+                }
+            }
+            if !same_geometry(&moved, roicol) {
+                *roicol = moved;
+                event = EditEvent::Changed;
+            }
+        }
+
+        if response.drag_released() && self.drag.take().is_some() {
+            event = EditEvent::Finished;
+        }
+
+        if response.clicked() {
+            self.selected = response.interact_pointer_pos().and_then(|pos| {
+                let (px, py) = canvas.to_image(pos);
+                roicol.roi_at(px, py)
+            });
+            if let Some((row, col)) = self.selected.filter(|_| ui.input(|i| i.modifiers.command)) {
+                roicol.set_excluded(row, col, !roicol.is_excluded(row, col));
+                event = EditEvent::Finished;
+            }
+        }
+
+        // keys typed into a text field, e.g. a ROI threshold, are not shortcuts
+        if let Some((row, col)) = self.selected.filter(|_| !ui.ctx().wants_keyboard_input()) {
+            let (ox, oy) = roicol
+                .offset(row, col)
+                .map(|o| (o.dx, o.dy))
+                .unwrap_or_default();
+            let nudge = ui.input(|i| {
+                let mut d = (0, 0);
+                if i.key_pressed(egui::Key::ArrowLeft) {
+                    d.0 -= 1;
+                }
+                if i.key_pressed(egui::Key::ArrowRight) {
+                    d.0 += 1;
+                }
+                if i.key_pressed(egui::Key::ArrowUp) {
+                    d.1 -= 1;
+                }
+                if i.key_pressed(egui::Key::ArrowDown) {
+                    d.1 += 1;
+                }
+                if i.key_pressed(egui::Key::Delete) {
+                    d = (-ox, -oy);
+                }
+                d
+            });
+            if nudge != (0, 0) {
+                roicol.set_offset(row, col, ox + nudge.0, oy + nudge.1);
+                event = EditEvent::Finished;
+            }
+        }
+
+        self.paint(ui, roicol, &canvas);
+        event
+    }
+
+    fn paint(&self, ui: &egui::Ui, roicol: &RoiCollection, canvas: &Canvas) {
+        let painter = ui.painter_at(canvas.rect);
+        let stroke = Stroke::new(1.5, Color32::from_rgb(0, 200, 255));
+        for (handle, pos) in handles(roicol, canvas) {
+            match handle {
+                Handle::Spacing => {
+                    let rect = Rect::from_center_size(pos, Vec2::splat(HANDLE_RADIUS * 2.));
+                    painter.rect_stroke(rect, 0.0, stroke);
+                }
+                Handle::Size => painter.circle_stroke(pos, HANDLE_RADIUS, stroke),
+                Handle::Rotate => {
+                    let r = HANDLE_RADIUS;
+                    let points = vec![
+                        pos + Vec2::new(0., -r),
+                        pos + Vec2::new(r, 0.),
+                        pos + Vec2::new(0., r),
+                        pos + Vec2::new(-r, 0.),
+                    ];
+                    painter.add(egui::Shape::closed_line(points, stroke));
+                }
+                _ => (),
+            }
+        }
+        if let Some(roi) = self.selected.and_then(|(row, col)| {
+            roicol
+                .rois()
+                .iter()
+                .find(|roi| roi.row == row && roi.col == col)
+        }) {
+            let min = canvas.to_screen((roi.x as f64, roi.y as f64));
+            let max = canvas.to_screen(((roi.x + roi.width) as f64, (roi.y + roi.height) as f64));
+            painter.rect_stroke(Rect::from_min_max(min, max), 0.0, stroke);
+        }
+    }
+}
+
+fn same_geometry(a: &RoiCollection, b: &RoiCollection) -> bool {
+    (a.x, a.y, a.xinterval, a.yinterval, a.width, a.height)
+        == (b.x, b.y, b.xinterval, b.yinterval, b.width, b.height)
+        && a.rotate == b.rotate
+        && a.offsets == b.offsets
+        && a.affine == b.affine
+}
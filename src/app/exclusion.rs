@@ -0,0 +1,103 @@
+//! Frames left out of the measurement, e.g. a lights-on flash, condensation
+//! on the lid or a hand over the plate.
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Name of the exclusions file in the data folder.
+pub const FILE: &str = "Excluded.json";
+
+/// Excluded frames of a dataset, stored in `Excluded.json`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Exclusions {
+    /// Inclusive ranges of frame indices, sorted and disjoint.
+    pub ranges: Vec<(usize, usize)>,
+}
+
+impl Exclusions {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let mut exclusions: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        // tolerate hand-edited files
+        let ranges = std::mem::take(&mut exclusions.ranges);
+        for (first, last) in ranges {
+            exclusions.exclude(first, last);
+        }
+        Ok(exclusions)
+    }
+
+    pub fn to_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, frame: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&frame))
+    }
+
+    /// Whether the pair of frames `previous` and `current` touches an
+    /// excluded frame.
+    pub fn touches(&self, previous: usize, current: usize) -> bool {
+        self.contains(previous) || self.contains(current)
+    }
+
+    /// Exclude the frames from `first` to `last`, merged with the ranges
+    /// they overlap or adjoin.
+    pub fn exclude(&mut self, first: usize, last: usize) {
+        let (mut first, mut last) = (first.min(last), first.max(last));
+        self.ranges.retain(|&(a, b)| {
+            let merge = a <= last.saturating_add(1) && first <= b.saturating_add(1);
+            if merge {
+                (first, last) = (first.min(a), last.max(b));
+            }
+            !merge
+        });
+        let idx = self.ranges.partition_point(|(a, _)| *a < first);
+        self.ranges.insert(idx, (first, last));
+    }
+
+    /// Include again the frames from `first` to `last`, splitting the ranges
+    /// around them.
+    pub fn include(&mut self, first: usize, last: usize) {
+        let (first, last) = (first.min(last), first.max(last));
+        self.ranges = self
+            .ranges
+            .iter()
+            .flat_map(|&(a, b)| {
+                let before = (a < first).then(|| (a, b.min(first - 1)));
+                let after = (b > last).then(|| (a.max(last + 1), b));
+                before.into_iter().chain(after)
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let mut exclusions = Exclusions::default();
+        exclusions.exclude(10, 12);
+        exclusions.exclude(20, 20);
+        exclusions.exclude(13, 14);
+        assert_eq!(exclusions.ranges, [(10, 14), (20, 20)]);
+        exclusions.exclude(18, 11);
+        assert_eq!(exclusions.ranges, [(10, 18), (20, 20)]);
+
+        exclusions.include(12, 13);
+        assert_eq!(exclusions.ranges, [(10, 11), (14, 18), (20, 20)]);
+        exclusions.include(20, 20);
+        assert_eq!(exclusions.ranges, [(10, 11), (14, 18)]);
+
+        assert!(exclusions.touches(9, 10));
+        assert!(!exclusions.touches(12, 13));
+    }
+}
@@ -0,0 +1,247 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::blobs::BlobStats;
+use super::calibration::Calibration;
+use super::pairs::PairSource;
+use super::plate::WellInfo;
+use super::process::{Algorithm, Morphology, Noise, Normalization};
+use super::roi::{Direction, RoiCollection};
+use super::threshold::AutoThreshold;
+
+/// Value written for the pairs touching an excluded frame.
+pub const NA: &str = "NA";
+
+/// Which tables are written in addition to the pixel counts of `Area.csv`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct AreaOutputs {
+    /// `AreaFraction.csv`: moving pixels over the measured pixels of each ROI.
+    pub fraction: bool,
+    /// `AreaUm2.csv`: moving area in µm², requires a calibration.
+    pub um2: bool,
+    /// `Blobs.csv`: connected components of the moving pixels of each ROI.
+    pub blobs: bool,
+    /// `QC.csv`: quality-control metrics and flags of every frame.
+    pub qc: bool,
+}
+
+/// Settings of a run, written next to its tables as `Parameters.json`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Parameters {
+    pub algorithm: Algorithm,
+    pub direction: Direction,
+    pub normalization: Normalization,
+    /// Noise of a fixed-scale run.
+    pub fixed_noise: Option<Noise>,
+    /// Whether each ROI is normalized on its own noise.
+    pub local_noise: bool,
+    /// Manual threshold, in std.
+    pub threshold: f64,
+    pub auto_threshold: AutoThreshold,
+    /// Automatic threshold selected once for the run.
+    pub run_threshold: Option<f64>,
+    /// Thresholds of the sweep, each with its own area table.
+    pub sweep: Vec<f64>,
+    /// Lags compared in addition to `step`.
+    pub lags: Vec<usize>,
+    /// Excluded ranges of frames, inclusive.
+    pub excluded: Vec<(usize, usize)>,
+    pub pair_source: PairSource,
+    /// Frames per burst, when pairing within bursts.
+    pub burst_size: usize,
+    pub morphology: Morphology,
+    pub start: usize,
+    pub end: usize,
+    pub step: usize,
+    pub calibration: Calibration,
+}
+
+impl Parameters {
+    pub fn to_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Threshold used to binarize each frame, for the automatic methods.
+pub fn write_thresholds<P: AsRef<Path>>(
+    path: P,
+    thresholds: &[(usize, Option<f64>)],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["frame", "threshold"])?;
+    for (frame, threshold) in thresholds {
+        let threshold = threshold.map_or(NA.to_owned(), |t| format!("{t:.4}"));
+        writer.write_record([frame.to_string(), threshold])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Frames subtracted for each row of the area tables, with their file names.
+pub fn write_frame_pairs<P: AsRef<Path>>(
+    path: P,
+    images: &[PathBuf],
+    pairs: &[(usize, usize)],
+) -> Result<(), Box<dyn Error>> {
+    let name = |idx: usize| {
+        images[idx]
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "row",
+        "previous",
+        "current",
+        "previous_file",
+        "current_file",
+    ])?;
+    for (row, (previous, current)) in pairs.iter().enumerate() {
+        writer.write_record([
+            (row + 1).to_string(),
+            previous.to_string(),
+            current.to_string(),
+            name(*previous),
+            name(*current),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Long-format table with one row per frame and measured ROI, including the
+/// plate map metadata of each well. Excluded frames (`None`) are `NA`.
+pub fn write_area_long<P: AsRef<Path>>(
+    path: P,
+    roicol: &RoiCollection,
+    frames: &[(usize, Option<Vec<u32>>)],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = vec!["frame", "label", "row", "col"];
+    header.extend(WellInfo::COLUMNS);
+    header.push("area");
+    writer.write_record(&header)?;
+
+    let rois: Vec<_> = roicol.measured().collect();
+    let empty = WellInfo::default();
+    for (frame, area) in frames {
+        let area: Vec<String> = match area {
+            Some(area) => area.iter().map(u32::to_string).collect(),
+            None => vec![NA.to_owned(); rois.len()],
+        };
+        for (roi, area) in rois.iter().zip(area) {
+            let info = roi.info.as_ref().unwrap_or(&empty);
+            let mut record = vec![
+                frame.to_string(),
+                roi.label.clone(),
+                roi.row.to_string(),
+                roi.col.to_string(),
+            ];
+            record.extend(info.values().map(str::to_owned));
+            record.push(area);
+            writer.write_record(&record)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Nominal and effective (clipped to the image) pixel area of every measured
+/// ROI, with the problems found by [`RoiCollection::check`].
+pub fn write_roi_areas<P: AsRef<Path>>(
+    path: P,
+    roicol: &RoiCollection,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["label", "row", "col", "area", "effective_area", "warnings"])?;
+
+    let warnings = roicol.check(width, height);
+    for roi in roicol.measured() {
+        let issues = warnings
+            .iter()
+            .filter(|w| w.row == roi.row && w.col == roi.col)
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        writer.write_record([
+            roi.label.clone(),
+            roi.row.to_string(),
+            roi.col.to_string(),
+            roi.pixel_area(u32::MAX, u32::MAX).to_string(),
+            roi.pixel_area(width, height).to_string(),
+            issues,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Table shaped like `Area.csv`, with the area of the `k`-th measured ROI
+/// converted by `convert(k, area)`. Excluded frames (`None`) and areas
+/// without a converted value are `NA`.
+pub fn write_area_table<P, F>(
+    path: P,
+    roicol: &RoiCollection,
+    frames: &[(usize, Option<Vec<u32>>)],
+    convert: F,
+) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: Fn(usize, u32) -> Option<f64>,
+{
+    let mut writer = csv::Writer::from_path(path)?;
+    let labels = roicol.labels();
+    writer.write_record(&labels)?;
+    for (_, area) in frames {
+        let record: Vec<String> = match area {
+            Some(area) => area
+                .iter()
+                .enumerate()
+                .map(|(k, area)| convert(k, *area).map_or(NA.to_owned(), |v| v.to_string()))
+                .collect(),
+            None => vec![NA.to_owned(); labels.len()],
+        };
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Long-format table of the connected components of each measured ROI.
+/// Excluded frames (`None`) are `NA`.
+pub fn write_blobs<P: AsRef<Path>>(
+    path: P,
+    roicol: &RoiCollection,
+    frames: &[(usize, Option<Vec<BlobStats>>)],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = vec!["frame", "label", "row", "col"];
+    header.extend(BlobStats::COLUMNS);
+    writer.write_record(&header)?;
+
+    let rois: Vec<_> = roicol.measured().collect();
+    for (frame, stats) in frames {
+        for (k, roi) in rois.iter().enumerate() {
+            let mut record = vec![
+                frame.to_string(),
+                roi.label.clone(),
+                roi.row.to_string(),
+                roi.col.to_string(),
+            ];
+            match stats {
+                Some(stats) => record.extend(stats[k].values()),
+                None => record.extend(BlobStats::COLUMNS.map(|_| NA.to_owned())),
+            }
+            writer.write_record(&record)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,422 @@
+//! Reading and writing ImageJ/Fiji `.roi` files and `RoiSet.zip` archives.
+//!
+//! Only the parts of the format needed for rectangles, ovals and polygons
+//! (including freehand and traced outlines) are supported, following
+//! `ij.io.RoiDecoder` and `ij.io.RoiEncoder`.
+use std::error::Error;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::roi::{FreeRoi, Shape};
+
+const VERSION: i16 = 228;
+const HEADER_SIZE: usize = 64;
+const HEADER2_SIZE: usize = 64;
+
+const TYPE: usize = 6;
+const TOP: usize = 8;
+const LEFT: usize = 10;
+const BOTTOM: usize = 12;
+const RIGHT: usize = 14;
+const N_COORDINATES: usize = 16;
+const HEADER2_OFFSET: usize = 60;
+const NAME_OFFSET: usize = 16;
+const NAME_LENGTH: usize = 20;
+
+const POLYGON: u8 = 0;
+const RECT: u8 = 1;
+const OVAL: u8 = 2;
+const FREEHAND: u8 = 7;
+const TRACED: u8 = 8;
+
+/// Name and content of a zip archive entry.
+type Entry = (String, Vec<u8>);
+
+fn get_i16(data: &[u8], offset: usize) -> Result<i16, Box<dyn Error>> {
+    data.get(offset..offset + 2)
+        .map(|b| i16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "truncated ImageJ ROI".into())
+}
+
+fn get_i32(data: &[u8], offset: usize) -> Result<i32, Box<dyn Error>> {
+    data.get(offset..offset + 4)
+        .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "truncated ImageJ ROI".into())
+}
+
+/// Offset of the second header, `None` when the ROI has none.
+fn header2(data: &[u8]) -> Result<Option<usize>, Box<dyn Error>> {
+    let header2 = get_i32(data, HEADER2_OFFSET)?;
+    if header2 == 0 {
+        return Ok(None);
+    }
+    match usize::try_from(header2) {
+        Ok(offset) if offset >= HEADER_SIZE && offset + HEADER2_SIZE <= data.len() => {
+            Ok(Some(offset))
+        }
+        _ => Err("truncated ImageJ ROI".into()),
+    }
+}
+
+fn read_name(data: &[u8], header2: usize) -> Option<String> {
+    let offset = usize::try_from(get_i32(data, header2 + NAME_OFFSET).ok()?).ok()?;
+    let length = usize::try_from(get_i32(data, header2 + NAME_LENGTH).ok()?).ok()?;
+    let bytes = data.get(offset..offset.checked_add(length.checked_mul(2)?)?)?;
+    let chars: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    String::from_utf16(&chars).ok()
+}
+
+/// Decode a single ImageJ ROI.
+pub fn decode(data: &[u8]) -> Result<FreeRoi, Box<dyn Error>> {
+    if data.get(0..4) != Some(b"Iout") {
+        return Err("not an ImageJ ROI".into());
+    }
+    if data.len() < HEADER_SIZE {
+        return Err("truncated ImageJ ROI".into());
+    }
+    let header2 = header2(data)?;
+    let roi_type = data[TYPE];
+    let top = get_i16(data, TOP)? as i32;
+    let left = get_i16(data, LEFT)? as i32;
+    let bottom = get_i16(data, BOTTOM)? as i32;
+    let right = get_i16(data, RIGHT)? as i32;
+    let shape = match roi_type {
+        RECT => Shape::Rectangle,
+        OVAL => Shape::Oval,
+        POLYGON | FREEHAND | TRACED => {
+            let n = get_i16(data, N_COORDINATES)? as u16 as usize;
+            let points = (0..n)
+                .map(|i| {
+                    let x = get_i16(data, HEADER_SIZE + 2 * i)? as i32;
+                    let y = get_i16(data, HEADER_SIZE + 2 * (n + i))? as i32;
+                    Ok([left + x, top + y])
+                })
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+            Shape::Polygon { points }
+        }
+        other => return Err(format!("unsupported ImageJ ROI type: {other}").into()),
+    };
+    Ok(FreeRoi {
+        name: header2
+            .and_then(|header2| read_name(data, header2))
+            .unwrap_or_default(),
+        x: left,
+        y: top,
+        width: (right - left).max(0) as u32,
+        height: (bottom - top).max(0) as u32,
+        shape,
+    })
+}
+
+/// Encode a ROI in the ImageJ binary format, whose coordinates are 16-bit.
+pub fn encode(roi: &FreeRoi) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (roi_type, points) = match &roi.shape {
+        Shape::Rectangle => (RECT, &[][..]),
+        Shape::Oval => (OVAL, &[][..]),
+        Shape::Polygon { points } => (POLYGON, &points[..]),
+    };
+    let n = points.len();
+    if n > u16::MAX as usize {
+        return Err(format!(
+            "ROI `{}` has {n} points, at most {} fit",
+            roi.name,
+            u16::MAX
+        )
+        .into());
+    }
+    let to_i16 = |v: i64| {
+        i16::try_from(v).map_err(|_| {
+            format!(
+                "ROI `{}`: coordinate {v} is outside the ImageJ range {}..={}",
+                roi.name,
+                i16::MIN,
+                i16::MAX
+            )
+        })
+    };
+    let (x, y) = (roi.x as i64, roi.y as i64);
+    let bounds = [
+        (TOP, to_i16(y)?),
+        (LEFT, to_i16(x)?),
+        (BOTTOM, to_i16(y + roi.height as i64)?),
+        (RIGHT, to_i16(x + roi.width as i64)?),
+    ];
+    let coordinates = points
+        .iter()
+        .map(|p| Ok((to_i16(p[0] as i64 - x)?, to_i16(p[1] as i64 - y)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let name: Vec<u16> = roi.name.encode_utf16().collect();
+    let header2 = HEADER_SIZE + 4 * n;
+    let mut data = vec![0u8; header2 + HEADER2_SIZE + 2 * name.len()];
+
+    let mut put_i16 =
+        |offset: usize, v: i16| data[offset..offset + 2].copy_from_slice(&v.to_be_bytes());
+    put_i16(4, VERSION);
+    for (offset, v) in bounds {
+        put_i16(offset, v);
+    }
+    put_i16(N_COORDINATES, n as u16 as i16);
+    for (i, (px, py)) in coordinates.into_iter().enumerate() {
+        put_i16(HEADER_SIZE + 2 * i, px);
+        put_i16(HEADER_SIZE + 2 * (n + i), py);
+    }
+    data[0..4].copy_from_slice(b"Iout");
+    data[TYPE] = roi_type;
+    data[HEADER2_OFFSET..HEADER2_OFFSET + 4].copy_from_slice(&(header2 as i32).to_be_bytes());
+    let name_offset = header2 + HEADER2_SIZE;
+    data[header2 + NAME_OFFSET..header2 + NAME_OFFSET + 4]
+        .copy_from_slice(&(name_offset as i32).to_be_bytes());
+    data[header2 + NAME_LENGTH..header2 + NAME_LENGTH + 4]
+        .copy_from_slice(&(name.len() as i32).to_be_bytes());
+    for (i, c) in name.iter().enumerate() {
+        data[name_offset + 2 * i..name_offset + 2 * i + 2].copy_from_slice(&c.to_be_bytes());
+    }
+    Ok(data)
+}
+
+/// Read ROIs from a `.roi` file or a `RoiSet.zip` archive.
+pub fn read_rois<P: AsRef<Path>>(path: P) -> Result<Vec<FreeRoi>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    if data.starts_with(b"Iout") {
+        let mut roi = decode(&data)?;
+        if roi.name.is_empty() {
+            roi.name = file_stem(&path.display().to_string());
+        }
+        return Ok(vec![roi]);
+    }
+    unzip(&data)?
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(".roi"))
+        .map(|(name, data)| {
+            let mut roi = decode(&data).map_err(|e| format!("{name}: {e}"))?;
+            if roi.name.is_empty() {
+                roi.name = file_stem(&name);
+            }
+            Ok(roi)
+        })
+        .collect()
+}
+
+/// Write a single `.roi` file, or a `RoiSet.zip` archive for any other extension.
+pub fn write_rois<P: AsRef<Path>>(path: P, rois: &[FreeRoi]) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|ext| ext == "roi") {
+        let [roi] = rois else {
+            return Err("a .roi file holds exactly one ROI, use a .zip archive".into());
+        };
+        std::fs::write(path, encode(roi)?)?;
+        return Ok(());
+    }
+    let entries: Vec<Entry> = rois
+        .iter()
+        .enumerate()
+        .map(|(i, roi)| {
+            let name = if roi.name.is_empty() {
+                format!("{:04}", i + 1)
+            } else {
+                roi.name.clone()
+            };
+            Ok((format!("{name}.roi"), encode(roi)?))
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    std::fs::write(path, zip(&entries)?)?;
+    Ok(())
+}
+
+fn file_stem(name: &str) -> String {
+    Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Result<usize, Box<dyn Error>> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+        .ok_or_else(|| "truncated zip archive".into())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Result<usize, Box<dyn Error>> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or_else(|| "truncated zip archive".into())
+}
+
+/// Entries of a zip archive, read through its central directory.
+fn unzip(data: &[u8]) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let eocd = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|&i| data[i..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or("not a zip archive")?;
+    let entries = le_u16(data, eocd + 10)?;
+    let mut offset = le_u32(data, eocd + 16)?;
+
+    let mut files = Vec::with_capacity(entries);
+    for _ in 0..entries {
+        if le_u32(data, offset)? != 0x02014b50 {
+            return Err("corrupted zip central directory".into());
+        }
+        let method = le_u16(data, offset + 10)?;
+        let compressed = le_u32(data, offset + 20)?;
+        let name_len = le_u16(data, offset + 28)?;
+        let extra_len = le_u16(data, offset + 30)?;
+        let comment_len = le_u16(data, offset + 32)?;
+        let local = le_u32(data, offset + 42)?;
+        let name = data
+            .get(offset + 46..offset + 46 + name_len)
+            .ok_or("truncated zip archive")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        offset += 46 + name_len + extra_len + comment_len;
+
+        let start = local + 30 + le_u16(data, local + 26)? + le_u16(data, local + 28)?;
+        let raw = data
+            .get(start..start + compressed)
+            .ok_or("truncated zip archive")?;
+        let content = match method {
+            0 => raw.to_vec(),
+            8 => {
+                let mut content = vec![];
+                flate2::read::DeflateDecoder::new(raw).read_to_end(&mut content)?;
+                content
+            }
+            other => return Err(format!("{name}: unsupported zip compression {other}").into()),
+        };
+        files.push((name, content));
+    }
+    Ok(files)
+}
+
+/// Deflate-compressed zip archive of the given entries.
+fn zip(entries: &[Entry]) -> Result<Vec<u8>, Box<dyn Error>> {
+    // 1980-01-01 00:00, the earliest date a zip archive can hold
+    const DATE: u16 = (1 << 5) | 1;
+    let mut out = vec![];
+    let mut central = vec![];
+    for (name, content) in entries {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(content)?;
+        let compressed = encoder.finish()?;
+        let crc = crc32fast::hash(content);
+        let offset = out.len() as u32;
+
+        let mut common = vec![];
+        common.extend(20u16.to_le_bytes()); // version needed to extract
+        common.extend(0u16.to_le_bytes()); // flags
+        common.extend(8u16.to_le_bytes()); // deflate
+        common.extend(0u16.to_le_bytes()); // time
+        common.extend(DATE.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend((compressed.len() as u32).to_le_bytes());
+        common.extend((content.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+        common.extend(0u16.to_le_bytes()); // extra field length
+
+        out.extend(0x04034b50u32.to_le_bytes());
+        out.extend(&common);
+        out.extend(name.as_bytes());
+        out.extend(&compressed);
+
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend(20u16.to_le_bytes()); // version made by
+        central.extend(&common);
+        central.extend([0u8; 10]); // comment length, disk, attributes
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+    }
+    let central_offset = out.len() as u32;
+    out.extend(&central);
+    out.extend(0x06054b50u32.to_le_bytes());
+    out.extend([0u8; 4]); // disk numbers
+    out.extend((entries.len() as u16).to_le_bytes());
+    out.extend((entries.len() as u16).to_le_bytes());
+    out.extend((central.len() as u32).to_le_bytes());
+    out.extend(central_offset.to_le_bytes());
+    out.extend(0u16.to_le_bytes()); // comment length
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roi_set_roundtrip() {
+        let rois = vec![
+            FreeRoi {
+                name: "A1".to_owned(),
+                x: 10,
+                y: 20,
+                width: 78,
+                height: 78,
+                shape: Shape::Rectangle,
+            },
+            FreeRoi {
+                name: "well-2".to_owned(),
+                x: 100,
+                y: 20,
+                width: 60,
+                height: 40,
+                shape: Shape::Oval,
+            },
+            FreeRoi {
+                name: String::new(),
+                x: 5,
+                y: 6,
+                width: 10,
+                height: 10,
+                shape: Shape::Polygon {
+                    points: vec![[5, 6], [15, 6], [5, 16]],
+                },
+            },
+        ];
+        let path = std::env::temp_dir().join("soustraire_RoiSet.zip");
+        write_rois(&path, &rois).expect("fail to write RoiSet.zip");
+        let decoded = read_rois(&path).expect("fail to read RoiSet.zip");
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(decoded[..2], rois[..2]);
+        // unnamed ROIs are named after their zip entry
+        assert_eq!(decoded[2].name, "0003");
+        assert_eq!(decoded[2].shape, rois[2].shape);
+    }
+
+    #[test]
+    fn test_decode_imagej_rect() {
+        // header of a 30x40 rectangle at (10, 20) saved by ImageJ without a name
+        let mut data = vec![0u8; 64];
+        data[0..4].copy_from_slice(b"Iout");
+        data[4..6].copy_from_slice(&227i16.to_be_bytes());
+        data[6] = RECT;
+        data[8..10].copy_from_slice(&20i16.to_be_bytes());
+        data[10..12].copy_from_slice(&10i16.to_be_bytes());
+        data[12..14].copy_from_slice(&60i16.to_be_bytes());
+        data[14..16].copy_from_slice(&40i16.to_be_bytes());
+        let roi = decode(&data).unwrap();
+        assert_eq!((roi.x, roi.y, roi.width, roi.height), (10, 20, 30, 40));
+        assert_eq!(roi.shape, Shape::Rectangle);
+        assert!(decode(b"PK\x03\x04").is_err());
+
+        // truncated files and second headers out of the file are errors
+        let named = encode(&FreeRoi {
+            name: "well".to_owned(),
+            ..roi.clone()
+        })
+        .unwrap();
+        assert_eq!(decode(&named).unwrap().name, "well");
+        for len in [4, 10, 63, named.len() - 2 * 4 - HEADER2_SIZE + 1] {
+            assert!(decode(&named[..len]).is_err(), "{len}");
+        }
+        data[HEADER2_OFFSET..HEADER2_OFFSET + 4].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(decode(&data).is_err());
+
+        // coordinates beyond 16 bits are errors, not truncated
+        let far = FreeRoi { x: 32_760, ..roi };
+        let err = encode(&far).unwrap_err().to_string();
+        assert!(err.contains("coordinate 32790"), "{err}");
+    }
+}
@@ -0,0 +1,119 @@
+//! Which pairs of frames a run subtracts.
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Name of the pair list in the data folder.
+pub const PAIR_LIST: &str = "Pairs.csv";
+
+/// How the pairs of frames of a run are chosen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PairSource {
+    /// Every `step` frames from start to end, each with the frame `step` later.
+    #[default]
+    Range,
+    /// As [`PairSource::Range`], without the pairs that span two bursts of
+    /// `burst_size` frames.
+    Bursts,
+    /// The pairs listed in `Pairs.csv` of the data folder.
+    List,
+}
+
+impl std::fmt::Display for PairSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairSource::Range => write!(f, "Range"),
+            PairSource::Bursts => write!(f, "Within bursts"),
+            PairSource::List => write!(f, "Pair list"),
+        }
+    }
+}
+
+/// Whether frames `a` and `b` belong to the same burst of `burst_size`
+/// frames, counted from the first frame of the stack.
+pub fn within_burst(a: usize, b: usize, burst_size: usize) -> bool {
+    burst_size == 0 || a / burst_size == b / burst_size
+}
+
+/// Pairs `(previous, current)` of frame indices read from a CSV file with
+/// two columns, holding either frame indices or file names of `images`. A
+/// first row with neither in both columns is taken as a header; any other
+/// row that is not a pair of two different frames is an error.
+pub fn read_pair_list<P: AsRef<Path>>(
+    path: P,
+    images: &[PathBuf],
+) -> Result<Vec<(usize, usize)>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let frame = |field: &str| -> Option<usize> {
+        match field.parse::<usize>() {
+            Ok(idx) => (idx < images.len()).then_some(idx),
+            Err(_) => images.iter().position(|image| {
+                image.file_name().is_some_and(|name| name == field) || image.as_os_str() == field
+            }),
+        }
+    };
+    let mut pairs = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let (Some(previous), Some(current)) = (record.get(0), record.get(1)) else {
+            return Err(format!("line {}: two columns are required", line + 1).into());
+        };
+        let is_header = |field: &str| field.parse::<usize>().is_err() && frame(field).is_none();
+        match (frame(previous), frame(current)) {
+            (Some(previous), Some(current)) if previous == current => {
+                return Err(format!(
+                    "line {}: frame {previous} cannot be subtracted from itself",
+                    line + 1
+                )
+                .into())
+            }
+            (Some(previous), Some(current)) => pairs.push((previous, current)),
+            _ if line == 0 && is_header(previous) && is_header(current) => (),
+            _ => {
+                return Err(format!(
+                    "line {}: `{previous}`, `{current}` are not frames of the stack",
+                    line + 1
+                )
+                .into())
+            }
+        }
+    }
+    if pairs.is_empty() {
+        return Err("the pair list is empty".into());
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_list() {
+        let images: Vec<PathBuf> = (0..20)
+            .map(|k| PathBuf::from(format!("/data/img{k:03}.jpg")))
+            .collect();
+        let path = std::env::temp_dir().join(format!("pairs-{}.csv", std::process::id()));
+        std::fs::write(&path, "previous,current\n0,1\nimg005.jpg, img009.jpg\n").unwrap();
+        assert_eq!(read_pair_list(&path, &images).unwrap(), [(0, 1), (5, 9)]);
+
+        std::fs::write(&path, "0,1\n3,42\n").unwrap();
+        let err = read_pair_list(&path, &images).unwrap_err().to_string();
+        assert!(err.starts_with("line 2"), "{err}");
+        // only a header may be skipped
+        std::fs::write(&path, "0,42\n0,1\n").unwrap();
+        let err = read_pair_list(&path, &images).unwrap_err().to_string();
+        assert!(err.starts_with("line 1"), "{err}");
+        std::fs::write(&path, "0,1\nimg003.jpg,3\n").unwrap();
+        let err = read_pair_list(&path, &images).unwrap_err().to_string();
+        assert!(err.starts_with("line 2") && err.contains("itself"), "{err}");
+        std::fs::remove_file(&path).ok();
+
+        // bursts of 10 frames
+        assert!(within_burst(8, 9, 10));
+        assert!(!within_burst(9, 10, 10));
+    }
+}
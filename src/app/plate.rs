@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Standard multiwell plate layouts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PlateFormat {
+    Well6,
+    Well12,
+    Well24,
+    Well48,
+    Well96,
+    Well384,
+}
+
+impl PlateFormat {
+    pub const ALL: [PlateFormat; 6] = [
+        PlateFormat::Well6,
+        PlateFormat::Well12,
+        PlateFormat::Well24,
+        PlateFormat::Well48,
+        PlateFormat::Well96,
+        PlateFormat::Well384,
+    ];
+
+    /// Number of (rows, columns) of the plate.
+    pub fn shape(self) -> (u32, u32) {
+        match self {
+            PlateFormat::Well6 => (2, 3),
+            PlateFormat::Well12 => (3, 4),
+            PlateFormat::Well24 => (4, 6),
+            PlateFormat::Well48 => (6, 8),
+            PlateFormat::Well96 => (8, 12),
+            PlateFormat::Well384 => (16, 24),
+        }
+    }
+}
+
+impl std::fmt::Display for PlateFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (nrow, ncol) = self.shape();
+        write!(f, "{} well", nrow * ncol)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberingOrder {
+    #[default]
+    RowMajor,
+    ColumnMajor,
+}
+
+impl std::fmt::Display for NumberingOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberingOrder::RowMajor => write!(f, "Row-major"),
+            NumberingOrder::ColumnMajor => write!(f, "Column-major"),
+        }
+    }
+}
+
+/// How ROIs are numbered and labelled.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Numbering {
+    pub order: NumberingOrder,
+    /// Index of the first ROI, 0 or 1.
+    pub start: usize,
+    /// Label ROIs with well names (A1, A2, ...) instead of their index.
+    pub well_names: bool,
+}
+
+impl Numbering {
+    pub fn index(&self, row: u32, col: u32, nrow: u32, ncol: u32) -> usize {
+        let idx = match self.order {
+            NumberingOrder::RowMajor => row * ncol + col,
+            NumberingOrder::ColumnMajor => col * nrow + row,
+        };
+        idx as usize + self.start
+    }
+
+    pub fn label(&self, row: u32, col: u32, index: usize) -> String {
+        if self.well_names {
+            well_name(row, col)
+        } else {
+            index.to_string()
+        }
+    }
+}
+
+/// Row letter(s) followed by the 1-based column, e.g. `A1`, `H12`, `P24`.
+pub fn well_name(row: u32, col: u32) -> String {
+    let mut letters = vec![];
+    let mut n = row + 1;
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        letters.push(b'A' + rem as u8);
+        n = (n - 1) / 26;
+    }
+    letters.reverse();
+    format!("{}{}", String::from_utf8_lossy(&letters), col + 1)
+}
+
+/// Metadata attached to a well by a plate map.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WellInfo {
+    pub strain: String,
+    pub genotype: String,
+    pub treatment: String,
+    pub replicate: String,
+}
+
+impl WellInfo {
+    pub const COLUMNS: [&'static str; 4] = ["strain", "genotype", "treatment", "replicate"];
+
+    pub fn values(&self) -> [&str; 4] {
+        [
+            &self.strain,
+            &self.genotype,
+            &self.treatment,
+            &self.replicate,
+        ]
+    }
+
+    /// Experimental group of the well; replicates of the same condition share a group.
+    pub fn group(&self) -> String {
+        [&self.strain, &self.genotype, &self.treatment]
+            .into_iter()
+            .filter(|v| !v.is_empty())
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+}
+
+/// Canonical form of a well name, e.g. `a01` -> `A1`.
+pub fn normalize_well(name: &str) -> String {
+    let name = name.trim().to_uppercase();
+    let split = name
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(name.len());
+    let (letters, digits) = name.split_at(split);
+    match digits.parse::<u32>() {
+        Ok(col) if !letters.is_empty() => format!("{letters}{col}"),
+        _ => name,
+    }
+}
+
+/// Read a plate map CSV with a `well` column and any of the `strain`,
+/// `genotype`, `treatment` and `replicate` columns (case-insensitive).
+pub fn read_plate_map<P: AsRef<Path>>(
+    path: P,
+) -> Result<BTreeMap<String, WellInfo>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let well = column("well").ok_or("plate map has no `well` column")?;
+    let columns = WellInfo::COLUMNS.map(column);
+
+    let mut map = BTreeMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let get = |idx: Option<usize>| {
+            idx.and_then(|i| record.get(i))
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let name = normalize_well(record.get(well).unwrap_or_default());
+        if name.is_empty() {
+            continue;
+        }
+        let [strain, genotype, treatment, replicate] = columns.map(get);
+        map.insert(
+            name,
+            WellInfo {
+                strain,
+                genotype,
+                treatment,
+                replicate,
+            },
+        );
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_well_names() {
+        assert_eq!(well_name(0, 0), "A1");
+        assert_eq!(well_name(7, 11), "H12");
+        assert_eq!(well_name(15, 23), "P24");
+        assert_eq!(well_name(26, 0), "AA1");
+
+        let numbering = Numbering {
+            order: NumberingOrder::ColumnMajor,
+            start: 1,
+            well_names: false,
+        };
+        assert_eq!(numbering.index(1, 0, 8, 12), 2);
+        assert_eq!(numbering.index(0, 1, 8, 12), 9);
+        assert_eq!(numbering.label(0, 1, 9), "9");
+    }
+
+    #[test]
+    fn test_read_plate_map() {
+        let path = std::env::temp_dir().join("soustraire_plate_map.csv");
+        std::fs::write(
+            &path,
+            "Well,Strain,Treatment,Replicate,Note\na01,N2,DMSO,1,x\nB12,CB1370,drug,2,\n",
+        )
+        .unwrap();
+        let map = read_plate_map(&path).expect("fail to read plate map");
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(map.len(), 2);
+        let info = &map["A1"];
+        assert_eq!(info.strain, "N2");
+        assert_eq!(info.genotype, "");
+        assert_eq!(info.group(), "N2 / DMSO");
+        assert_eq!(map["B12"].replicate, "2");
+    }
+}
@@ -1,6 +1,28 @@
 use image::{ImageBuffer, Luma};
 use imageproc::filter;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+/// Implementation used to subtract and normalize a pair of frames.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    #[default]
+    Native,
+    /// Follow the steps of the original ImageJ imagesubtractor plugin: ImageJ's
+    /// gray conversion, sample std, 8-bit scaling and circular median.
+    ImageJ,
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Algorithm::Native => write!(f, "Native"),
+            Algorithm::ImageJ => write!(f, "ImageJ style"),
+        }
+    }
+}
 
 pub fn imread_as_gray<P: AsRef<Path>>(
     path: P,
@@ -8,6 +30,24 @@ pub fn imread_as_gray<P: AsRef<Path>>(
     Ok(image::open(path)?.grayscale().to_luma8())
 }
 
+/// Read an image as 8-bit gray the way ImageJ converts it, with unweighted
+/// RGB averaging (`(r + g + b) / 3`, rounded half up).
+pub fn imread_as_gray_imagej<P: AsRef<Path>>(path: P) -> Result<GrayImage, image::ImageError> {
+    let im = image::open(path)?;
+    if !im.color().has_color() {
+        return Ok(im.to_luma8());
+    }
+    let rgb = im.to_rgb8();
+    let mut gray = GrayImage::new(rgb.width(), rgb.height());
+    gray.iter_mut()
+        .zip(rgb.pixels())
+        .for_each(|(dst, image::Rgb([r, g, b]))| {
+            let w = 1. / 3.;
+            *dst = (*r as f64 * w + *g as f64 * w + *b as f64 * w + 0.5) as u8;
+        });
+    Ok(gray)
+}
+
 fn _imread<P: AsRef<Path>>(
     path: P,
 ) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, image::ImageError> {
@@ -16,62 +56,677 @@ fn _imread<P: AsRef<Path>>(
 
 type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
 
-pub fn subtract_core(im1: &GrayImage, im2: &GrayImage) -> GrayImage {
-    let width = im1.width();
-    let height = im1.height();
+/// Estimate of the noise level that scales the difference of two frames.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Standard deviation of the whole difference, as in imagesubtractor.
+    #[default]
+    Std,
+    /// Median absolute deviation around the median, scaled to a std.
+    Mad,
+    /// Standard deviation of the central 90 % of the differences.
+    TrimmedStd,
+    /// Standard deviation of the pixels outside every ROI.
+    Background,
+}
+
+impl std::fmt::Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Normalization::Std => write!(f, "Std"),
+            Normalization::Mad => write!(f, "Median/MAD"),
+            Normalization::TrimmedStd => write!(f, "Trimmed std"),
+            Normalization::Background => write!(f, "Background std"),
+        }
+    }
+}
 
-    let mut sub: ImageBuffer<Luma<i16>, Vec<i16>> = ImageBuffer::new(width, height);
+/// Center and spread of the difference between two frames.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Noise {
+    pub center: f64,
+    pub sigma: f64,
+}
 
-    let sum = im1
-        .iter()
+/// Pixel differences `im1 - im2`, in `-255..=255`.
+pub fn delta(im1: &GrayImage, im2: &GrayImage) -> Vec<i16> {
+    im1.iter()
         .zip(im2.iter())
-        .zip(sub.iter_mut())
-        .fold(0_i64, |acc, ((v1, v2), dst)| {
-            let delta = *v1 as i16 - *v2 as i16;
-            *dst = delta;
-            acc + delta as i64
-        }) as f64;
+        .map(|(v1, v2)| *v1 as i16 - *v2 as i16)
+        .collect()
+}
 
-    let count = (width * height) as f64;
-    let mean = sum / count;
+/// Histogram of differences, bin 0 is -255.
+struct Histogram([u64; 511]);
 
-    let std = (sub
-        .iter()
-        .cloned()
-        .fold(0., |acc, v| acc + (mean - v as f64).powi(2))
-        / count)
-        .sqrt();
-    // normalize to 20 times std
-    let vmin = -10f64 * std;
-    let vmax = 10f64 * std;
-    let delta = vmax - vmin;
+impl Histogram {
+    fn new(values: impl Iterator<Item = i16>) -> Self {
+        let mut bins = [0; 511];
+        values.for_each(|v| bins[(v + 255) as usize] += 1);
+        Self(bins)
+    }
+
+    fn count(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    /// Smallest value with at least `rank + 1` values up to it.
+    fn nth(&self, rank: u64) -> i16 {
+        let mut acc = 0;
+        for (bin, n) in self.0.iter().enumerate() {
+            acc += n;
+            if acc > rank {
+                return bin as i16 - 255;
+            }
+        }
+        255
+    }
+
+    /// Mean and population std of the values ranked `lo..hi`.
+    fn moments(&self, lo: u64, hi: u64) -> (f64, f64) {
+        let (mut rank, mut n, mut sum, mut sum2) = (0u64, 0f64, 0f64, 0f64);
+        for (bin, count) in self.0.iter().enumerate() {
+            let taken = (rank + count).min(hi).saturating_sub(rank.max(lo));
+            rank += count;
+            let v = bin as f64 - 255.;
+            n += taken as f64;
+            sum += taken as f64 * v;
+            sum2 += taken as f64 * v * v;
+        }
+        if n == 0. {
+            return (0., 0.);
+        }
+        let mean = sum / n;
+        (mean, (sum2 / n - mean * mean).max(0.).sqrt())
+    }
+}
+
+impl Noise {
+    /// Noise of `values`, the differences of the pixels used for the
+    /// estimate. `Std` and `Background` keep the zero center of
+    /// imagesubtractor, the robust estimates center on the bulk of the
+    /// values, which follows a global change of brightness.
+    pub fn estimate(values: &[i16], method: Normalization) -> Self {
+        let hist = Histogram::new(values.iter().copied());
+        let n = hist.count();
+        let std = || Self {
+            center: 0.,
+            sigma: hist.moments(0, n).1,
+        };
+        match method {
+            Normalization::Std | Normalization::Background => std(),
+            Normalization::Mad => {
+                let median = hist.nth(n.saturating_sub(1) / 2);
+                let deviations = Histogram::new(values.iter().map(|v| (v - median).abs()));
+                let mad = deviations.nth(n.saturating_sub(1) / 2);
+                if mad == 0 {
+                    // more than half of the pixels are unchanged
+                    return Self {
+                        center: median as f64,
+                        ..std()
+                    };
+                }
+                Self {
+                    center: median as f64,
+                    sigma: 1.4826 * mad as f64,
+                }
+            }
+            Normalization::TrimmedStd => {
+                let cut = n / 20;
+                let (mean, std) = hist.moments(cut, n - cut);
+                // std of a normal distribution truncated at its 5 % tails
+                Self {
+                    center: mean,
+                    sigma: std / 0.7894,
+                }
+            }
+        }
+    }
+
+    /// Median of the noise of several pairs, for a fixed scale over a run.
+    pub fn calibrate(samples: &[Noise]) -> Option<Self> {
+        let median = |mut v: Vec<f64>| {
+            v.sort_by(|a, b| a.total_cmp(b));
+            v[v.len() / 2]
+        };
+        (!samples.is_empty()).then(|| Self {
+            center: median(samples.iter().map(|n| n.center).collect()),
+            sigma: median(samples.iter().map(|n| n.sigma).collect()),
+        })
+    }
+}
+
+/// Turns the difference of a pair of frames into an 8-bit image.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalizer {
+    pub method: Normalization,
+    /// Noise calibrated once for the run, used instead of the estimate of
+    /// each pair.
+    pub fixed: Option<Noise>,
+    /// Pixels outside every ROI, for [`Normalization::Background`].
+    pub background: Option<Arc<[bool]>>,
+    /// Pixel indices of regions, e.g. ROIs, normalized on their own noise
+    /// instead of the one of the whole frame.
+    pub regions: Option<Arc<[Vec<u32>]>>,
+    /// Noise of each region calibrated once for the run.
+    pub fixed_regions: Option<Arc<[Noise]>>,
+}
+
+impl Normalizer {
+    /// Noise estimated from `delta` alone, ignoring [`Normalizer::fixed`].
+    pub fn estimate(&self, delta: &[i16]) -> Noise {
+        match (self.method, &self.background) {
+            (Normalization::Background, Some(background))
+                if background.len() == delta.len() && background.iter().any(|b| *b) =>
+            {
+                let values: Vec<i16> = delta
+                    .iter()
+                    .zip(background.iter())
+                    .filter_map(|(v, b)| b.then_some(*v))
+                    .collect();
+                Noise::estimate(&values, self.method)
+            }
+            _ => Noise::estimate(delta, self.method),
+        }
+    }
+
+    pub fn noise(&self, delta: &[i16]) -> Noise {
+        self.fixed.unwrap_or_else(|| self.estimate(delta))
+    }
+
+    /// Text identifying the normalization, for [`super::diffcache::DiffCache`].
+    /// The masks and the noise of the regions enter through their CRC-32.
+    pub fn cache_key(&self) -> String {
+        let mut hasher = crc32fast::Hasher::new();
+        if let Some(background) = &self.background {
+            let bytes: Vec<u8> = background.iter().map(|b| *b as u8).collect();
+            hasher.update(&bytes);
+        }
+        for region in self.regions.iter().flat_map(|regions| regions.iter()) {
+            hasher.update(&(region.len() as u32).to_le_bytes());
+            region
+                .iter()
+                .for_each(|idx| hasher.update(&idx.to_le_bytes()));
+        }
+        for noise in self.fixed_regions.iter().flat_map(|noise| noise.iter()) {
+            hasher.update(&noise.center.to_le_bytes());
+            hasher.update(&noise.sigma.to_le_bytes());
+        }
+        format!(
+            "{:?}|{:?}|{:08x}",
+            self.method,
+            self.fixed,
+            hasher.finalize()
+        )
+    }
+
+    /// Noise of each of the [`Normalizer::regions`] estimated from `delta`,
+    /// ignoring [`Normalizer::fixed_regions`].
+    pub fn estimate_regions(&self, delta: &[i16]) -> Vec<Noise> {
+        let Some(regions) = self.regions.as_ref() else {
+            return vec![];
+        };
+        regions
+            .iter()
+            .map(|region| {
+                let values: Vec<i16> = region.iter().map(|idx| delta[*idx as usize]).collect();
+                Noise::estimate(&values, self.method)
+            })
+            .collect()
+    }
+
+    /// Map `center - 10 sigma..center + 10 sigma` of `delta` to `0..=255`,
+    /// region by region when there are [`Normalizer::regions`].
+    pub fn normalize(&self, delta: &[i16], width: u32, height: u32) -> GrayImage {
+        let frame_lut = lut(self.noise(delta));
+        let mut sub_norm = GrayImage::new(width, height);
+        sub_norm
+            .iter_mut()
+            .zip(delta)
+            .for_each(|(dst, src)| *dst = frame_lut[(src + 255) as usize]);
+
+        if let Some(regions) = self.regions.as_ref() {
+            let noise = match self.fixed_regions.as_ref() {
+                Some(fixed) if fixed.len() == regions.len() => fixed.to_vec(),
+                _ => self.estimate_regions(delta),
+            };
+            let dst: &mut [u8] = &mut sub_norm;
+            for (region, noise) in regions.iter().zip(noise) {
+                let region_lut = lut(noise);
+                region.iter().for_each(|idx| {
+                    dst[*idx as usize] = region_lut[(delta[*idx as usize] + 255) as usize]
+                });
+            }
+        }
+        sub_norm
+    }
+}
+
+/// Gray level of every difference `-255..=255`, with
+/// `center - 10 sigma..center + 10 sigma` mapped to `0..=255`.
+fn lut(Noise { center, sigma }: Noise) -> [u8; 511] {
+    let vmin = center - 10f64 * sigma;
+    let vmax = center + 10f64 * sigma;
+    let range = vmax - vmin;
 
     let mut lut: [u8; 511] = [0; 511];
-    // (0usize..512).for_each(|v|  (v as f64-255.0 - mean) - vmin  )
-    if delta.is_normal() {
+    if range.is_normal() {
         lut.iter_mut().enumerate().for_each(|(val, lut)| {
-            *lut = ((val as f64 - 255.0 - vmin) / delta * 255.)
+            *lut = ((val as f64 - 255.0 - vmin) / range * 255.)
                 .clamp(0., 255.)
                 .round() as u8;
         });
     }
+    lut
+}
 
-    let mut sub_norm = ImageBuffer::new(width, height);
-    sub_norm
-        .iter_mut()
-        .zip(sub.iter())
-        .for_each(|(dst, src)| *dst = lut[(src + 255) as usize]);
-
+pub fn subtract_core(im1: &GrayImage, im2: &GrayImage, normalizer: &Normalizer) -> GrayImage {
+    let sub_norm = normalizer.normalize(&delta(im1, im2), im1.width(), im1.height());
     // radius = 2 is equivalent to k_size = 5,
     filter::median_filter(&sub_norm, 2, 2)
 }
 
+/// ImageJ version of [`subtract_core`]: 32-bit float difference, sample
+/// standard deviation, ImageJ's float to 8-bit scaling and a median filter
+/// over the circular kernel of `RankFilters` (radius 2, 21 pixels).
+pub fn subtract_imagej(im1: &GrayImage, im2: &GrayImage) -> GrayImage {
+    let width = im1.width();
+    let height = im1.height();
+
+    let sub: Vec<f32> = im1
+        .iter()
+        .zip(im2.iter())
+        .map(|(v1, v2)| *v1 as f32 - *v2 as f32)
+        .collect();
+
+    // ImageStatistics accumulates in double and divides by n - 1
+    let n = sub.len() as f64;
+    let (sum, sum2) = sub.iter().fold((0f64, 0f64), |(sum, sum2), v| {
+        (sum + *v as f64, sum2 + (*v as f64) * (*v as f64))
+    });
+    let std = if n > 1. {
+        ((n * sum2 - sum * sum) / n / (n - 1.)).max(0.).sqrt()
+    } else {
+        0.
+    };
+
+    // FloatProcessor.create8BitImage after setMinAndMax(-10 std, 10 std)
+    let min = (-10. * std) as f32;
+    let max = (10. * std) as f32;
+    let scale = 255f32 / (max - min);
+    let mut sub_norm = GrayImage::new(width, height);
+    sub_norm.iter_mut().zip(&sub).for_each(|(dst, v)| {
+        let value = (v - min) * scale + 0.5;
+        *dst = if value.is_finite() {
+            value.clamp(0., 255.) as u8
+        } else {
+            0
+        };
+    });
+
+    median_circular(&sub_norm)
+}
+
+/// Median over the ImageJ circular kernel of radius 2, out-of-image pixels
+/// replaced by the nearest edge pixel.
+fn median_circular(im: &GrayImage) -> GrayImage {
+    // half width of each kernel row for dy = -2..=2
+    const ROWS: [i64; 5] = [1, 2, 2, 2, 1];
+    let (width, height) = (im.width() as i64, im.height() as i64);
+    let mut out = GrayImage::new(im.width(), im.height());
+    let mut values = Vec::with_capacity(21);
+    for y in 0..height {
+        for x in 0..width {
+            values.clear();
+            for (dy, half) in (-2..=2).zip(ROWS) {
+                let yy = (y + dy).clamp(0, height - 1) as u32;
+                for dx in -half..=half {
+                    let xx = (x + dx).clamp(0, width - 1) as u32;
+                    values.push(im.get_pixel(xx, yy).0[0]);
+                }
+            }
+            let mid = values.len() / 2;
+            let (_, median, _) = values.select_nth_unstable(mid);
+            out.put_pixel(x as u32, y as u32, Luma([*median]));
+        }
+    }
+    out
+}
+
+/// Clean-up of the binarized difference image before the ROIs are counted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Morphology {
+    /// Radius of the opening, which removes specks thinner than the kernel.
+    pub open: u8,
+    /// Radius of the closing, which fills small holes and gaps.
+    pub close: u8,
+    /// Moving blobs (8-connected) smaller than this many pixels are dropped.
+    pub min_blob_size: u32,
+}
+
+impl Morphology {
+    /// Opening, then closing, then the blob size filter, on a mask whose
+    /// moving pixels are 255.
+    pub fn apply(&self, mask: &mut GrayImage) {
+        use imageproc::distance_transform::Norm;
+        if self.open > 0 {
+            imageproc::morphology::open_mut(mask, Norm::LInf, self.open);
+        }
+        if self.close > 0 {
+            imageproc::morphology::close_mut(mask, Norm::LInf, self.close);
+        }
+        if self.min_blob_size > 1 {
+            let labels = imageproc::region_labelling::connected_components(
+                mask,
+                imageproc::region_labelling::Connectivity::Eight,
+                Luma([0u8]),
+            );
+            let mut sizes = vec![0u32; labels.iter().max().map_or(0, |max| *max as usize + 1)];
+            labels.iter().for_each(|label| sizes[*label as usize] += 1);
+            mask.iter_mut().zip(labels.iter()).for_each(|(pix, label)| {
+                if *label > 0 && sizes[*label as usize] < self.min_blob_size {
+                    *pix = 0;
+                }
+            });
+        }
+    }
+}
+
 // /// Formats the sum of two numbers as string.
 pub fn subtract<P: AsRef<Path>>(
     img1_path: P,
     img2_path: P,
+    algorithm: Algorithm,
+    normalizer: &Normalizer,
+) -> Result<GrayImage, image::ImageError> {
+    let im1 = imread(img1_path, algorithm)?;
+    let im2 = imread(img2_path, algorithm)?;
+    Ok(subtract_gray(&im1, &im2, algorithm, normalizer))
+}
+
+/// Read a frame as gray the way `algorithm` does.
+pub fn imread<P: AsRef<Path>>(
+    path: P,
+    algorithm: Algorithm,
 ) -> Result<GrayImage, image::ImageError> {
-    let im1 = imread_as_gray(img1_path)?;
-    let im2 = imread_as_gray(img2_path)?;
-    Ok(subtract_core(&im1, &im2))
+    match algorithm {
+        Algorithm::Native => imread_as_gray(path),
+        Algorithm::ImageJ => imread_as_gray_imagej(path),
+    }
+}
+
+/// [`subtract`] of frames already read by [`imread`].
+pub fn subtract_gray(
+    im1: &GrayImage,
+    im2: &GrayImage,
+    algorithm: Algorithm,
+    normalizer: &Normalizer,
+) -> GrayImage {
+    match algorithm {
+        Algorithm::Native => subtract_core(im1, im2, normalizer),
+        // the plugin's own std, for the same counts as ImageJ
+        Algorithm::ImageJ => subtract_imagej(im1, im2),
+    }
+}
+
+/// Frames of a run, each read at most once, on first use, and shared by all
+/// the pairs it is part of.
+pub struct FrameSet<'a> {
+    paths: &'a [PathBuf],
+    algorithm: Algorithm,
+    frames: HashMap<usize, OnceLock<GrayImage>>,
+}
+
+impl<'a> FrameSet<'a> {
+    /// Frames `indices` of `paths`.
+    pub fn new(
+        paths: &'a [PathBuf],
+        indices: impl IntoIterator<Item = usize>,
+        algorithm: Algorithm,
+    ) -> Self {
+        Self {
+            paths,
+            algorithm,
+            frames: indices
+                .into_iter()
+                .map(|idx| (idx, OnceLock::new()))
+                .collect(),
+        }
+    }
+
+    /// Frame `idx`, which must be one of the indices of the set.
+    pub fn get(&self, idx: usize) -> &GrayImage {
+        self.frames[&idx]
+            .get_or_init(|| imread(&self.paths[idx], self.algorithm).expect("fail to open image"))
+    }
+}
+
+/// Bytes of decoded frames a run keeps in memory at once to share them
+/// between the pairs of several lags.
+pub const FRAME_BUDGET: usize = 1 << 30;
+
+/// Consecutive chunks of `pairs`, each with at most `max_frames` distinct
+/// frames unless a single pair has more.
+pub fn frame_chunks<T>(
+    pairs: &[T],
+    frames: impl Fn(&T) -> [usize; 2],
+    max_frames: usize,
+) -> Vec<&[T]> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut seen = std::collections::HashSet::new();
+    for (k, pair) in pairs.iter().enumerate() {
+        let [a, b] = frames(pair);
+        let new = usize::from(!seen.contains(&a)) + usize::from(a != b && !seen.contains(&b));
+        if k > start && seen.len() + new > max_frames {
+            chunks.push(&pairs[start..k]);
+            start = k;
+            seen.clear();
+        }
+        seen.extend([a, b]);
+    }
+    if start < pairs.len() {
+        chunks.push(&pairs[start..]);
+    }
+    chunks
+}
+
+/// Noise of the whole frame and of each region for a fixed-scale run.
+pub type FixedNoise = (Noise, Vec<Noise>);
+
+/// Noise of up to `samples` of the frame `pairs`, spread evenly, combined
+/// by [`Noise::calibrate`] for the frame and for each region.
+pub fn calibrate_noise<P: AsRef<Path> + Sync>(
+    pairs: &[(P, P)],
+    normalizer: &Normalizer,
+    samples: usize,
+) -> Result<Option<FixedNoise>, image::ImageError> {
+    use rayon::prelude::*;
+    let stride = (pairs.len() / samples.max(1)).max(1);
+    let noise = pairs
+        .par_iter()
+        .step_by(stride)
+        .map(|(p1, p2)| {
+            let (im1, im2) = (imread_as_gray(p1)?, imread_as_gray(p2)?);
+            let delta = delta(&im1, &im2);
+            Ok((
+                normalizer.estimate(&delta),
+                normalizer.estimate_regions(&delta),
+            ))
+        })
+        .collect::<Result<Vec<_>, image::ImageError>>()?;
+    let Some(frame) = Noise::calibrate(&noise.iter().map(|(n, _)| *n).collect::<Vec<_>>()) else {
+        return Ok(None);
+    };
+    let regions = (0..noise[0].1.len())
+        .filter_map(|k| Noise::calibrate(&noise.iter().map(|(_, r)| r[k]).collect::<Vec<_>>()))
+        .collect();
+    Ok(Some((frame, regions)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::iproduct;
+
+    /// Two noisy RGB frames with a dark blob that moves between them.
+    fn noisy_frames(name: &str) -> Vec<PathBuf> {
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 24) as u8 / 8
+        };
+        (0..2)
+            .map(|frame| {
+                let im = image::RgbImage::from_fn(64, 48, |x, y| {
+                    let blob =
+                        (20 + frame * 8..30 + frame * 8).contains(&x) && (20..26).contains(&y);
+                    let base = if blob { 40 } else { 120 };
+                    image::Rgb([base + noise(), base + noise(), base + noise()])
+                });
+                let path =
+                    std::env::temp_dir().join(format!("{name}-{}-{frame}.png", std::process::id()));
+                im.save(&path).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_morphology() {
+        let mut mask = GrayImage::new(12, 8);
+        // a 4x4 blob, a lone pixel and a 2-pixel speck
+        for (x, y) in iproduct!(1..5, 1..5).chain([(8, 1), (9, 5), (10, 5)]) {
+            mask.put_pixel(x, y, Luma([255]));
+        }
+        let count = |mask: &GrayImage| mask.iter().filter(|v| **v > 0).count();
+
+        let mut filtered = mask.clone();
+        Morphology {
+            min_blob_size: 3,
+            ..Default::default()
+        }
+        .apply(&mut filtered);
+        assert_eq!(count(&filtered), 16);
+
+        let mut opened = mask.clone();
+        Morphology {
+            open: 1,
+            ..Default::default()
+        }
+        .apply(&mut opened);
+        assert_eq!(count(&opened), 16);
+
+        let mut unchanged = mask.clone();
+        Morphology::default().apply(&mut unchanged);
+        assert_eq!(unchanged, mask);
+    }
+
+    #[test]
+    fn test_noise_estimates() {
+        // noise around +1, then 5 % of the pixels moving a lot
+        let mut values: Vec<i16> = (0..1000).map(|k| [0, 1, 1, 2][k % 4]).collect();
+        let std = Noise::estimate(&values, Normalization::Std);
+        assert_eq!(std.center, 0.);
+        assert!((std.sigma - 0.5f64.sqrt()).abs() < 1e-9);
+
+        values[..50].fill(-100);
+        let std = Noise::estimate(&values, Normalization::Std);
+        let mad = Noise::estimate(&values, Normalization::Mad);
+        let trimmed = Noise::estimate(&values, Normalization::TrimmedStd);
+        assert!(std.sigma > 20.);
+        assert_eq!(mad.center, 1.);
+        assert!((mad.sigma - 1.4826).abs() < 1e-9);
+        assert!(trimmed.sigma < 2. && (trimmed.center - 1.).abs() < 0.2);
+
+        // only the pixels outside the ROIs
+        let normalizer = Normalizer {
+            method: Normalization::Background,
+            background: Some((0..1000).map(|k| k >= 50).collect()),
+            ..Default::default()
+        };
+        assert!((normalizer.estimate(&values).sigma - 0.5f64.sqrt()).abs() < 1e-2);
+        let fixed = Normalizer {
+            fixed: Noise::calibrate(&[std, mad, trimmed]),
+            ..normalizer
+        };
+        // medians of the centers and of the spreads
+        let noise = Noise {
+            center: trimmed.center,
+            sigma: mad.sigma,
+        };
+        assert_eq!(fixed.noise(&values), noise);
+
+        // a quiet region keeps its own, smaller scale
+        let regions = Normalizer {
+            regions: Some(vec![(500..1000).collect()].into()),
+            ..Default::default()
+        };
+        let region = regions.estimate_regions(&values)[0];
+        assert!((region.sigma - 0.5f64.sqrt()).abs() < 1e-2);
+        let norm = regions.normalize(&values, 1000, 1);
+        let scaled =
+            |noise: Noise, v: f64| ((v + 10. * noise.sigma) / (20. * noise.sigma) * 255.).round();
+        assert_eq!(norm.get_pixel(0, 0)[0] as f64, scaled(std, -100.).max(0.));
+        assert_eq!(norm.get_pixel(503, 0)[0] as f64, scaled(region, 2.));
+    }
+
+    #[test]
+    fn test_frame_set() {
+        let paths = noisy_frames("frame-set");
+        let frames = FrameSet::new(&paths, [0, 1, 1], Algorithm::ImageJ);
+        let subimg = subtract_gray(
+            frames.get(0),
+            frames.get(1),
+            Algorithm::ImageJ,
+            &Normalizer::default(),
+        );
+        let direct = subtract(
+            &paths[0],
+            &paths[1],
+            Algorithm::ImageJ,
+            &Normalizer::default(),
+        );
+        assert_eq!(subimg, direct.unwrap());
+        // read once and shared
+        assert!(std::ptr::eq(frames.get(1), frames.get(1)));
+        paths
+            .iter()
+            .for_each(|path| std::fs::remove_file(path).unwrap());
+
+        // pairs with lags 1 and 3, at most 4 frames at once
+        let pairs = [(0, 1), (0, 3), (1, 2), (1, 4), (2, 3), (2, 5)];
+        let chunks = frame_chunks(&pairs, |(a, b)| [*a, *b], 4);
+        assert_eq!(chunks, [&pairs[..3], &pairs[3..5], &pairs[5..]]);
+        assert_eq!(frame_chunks(&pairs, |(a, b)| [*a, *b], 1).len(), 6);
+    }
+
+    #[test]
+    fn test_subtract_imagej() {
+        // unweighted RGB average
+        let path = std::env::temp_dir().join(format!("red-{}.png", std::process::id()));
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+        assert_eq!(imread_as_gray_imagej(&path).unwrap().get_pixel(0, 0)[0], 85);
+        std::fs::remove_file(&path).unwrap();
+
+        // differences of +1 on the left and -1 on the right: std is
+        // sqrt(400 / 399), so +-1 std maps to 127.5 +- 12.73, then + 0.5
+        let im1 = GrayImage::from_fn(20, 20, |x, _| Luma([if x < 10 { 101 } else { 99 }]));
+        let im2 = GrayImage::from_pixel(20, 20, Luma([100]));
+        let subimg = subtract_imagej(&im1, &im2);
+        assert_eq!(subimg.get_pixel(2, 10)[0], 140);
+        assert_eq!(subimg.get_pixel(17, 10)[0], 115);
+
+        // 13 of the 25 pixels around the center are set, 4 of them in the
+        // corners that the 21-pixel circular kernel leaves out
+        let im = GrayImage::from_fn(5, 5, |x, y| {
+            Luma([if y == 0 || y == 4 || x == 0 { 255 } else { 0 }])
+        });
+        assert_eq!(im.iter().filter(|v| **v > 0).count(), 13);
+        assert_eq!(filter::median_filter(&im, 2, 2).get_pixel(2, 2)[0], 255);
+        assert_eq!(median_circular(&im).get_pixel(2, 2)[0], 0);
+    }
 }
@@ -0,0 +1,432 @@
+//! Quality control of the frames of a run: metrics of every pair and flags
+//! for the frames that are likely to give spurious areas.
+use image::{ImageBuffer, Luma};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::export::NA;
+use super::process::{self, Noise, Normalization};
+
+type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
+
+/// Why a frame is flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// The mean intensity changed far more than between the other frames.
+    ExposureJump,
+    /// The frame is identical to the one it is compared with.
+    Duplicate,
+    /// The time since the previous frame is longer than expected, only with
+    /// the acquisition times of the frames.
+    Dropped,
+    /// Much less sharp than the other frames.
+    Blurred,
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flag::ExposureJump => write!(f, "exposure jump"),
+            Flag::Duplicate => write!(f, "duplicate"),
+            Flag::Dropped => write!(f, "dropped frames"),
+            Flag::Blurred => write!(f, "blurred"),
+        }
+    }
+}
+
+/// Metrics of the pair of frames `previous` and `frame`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameQc {
+    pub frame: usize,
+    pub previous: usize,
+    /// Mean gray level of the frame.
+    pub mean_intensity: f64,
+    /// Change of the mean gray level since the previous frame.
+    pub intensity_change: f64,
+    /// Std of the pixel differences, as normalized by `Std`.
+    pub delta_std: f64,
+    /// Share of the measured ROIs with moving pixels.
+    pub active_fraction: f64,
+    /// Variance of the Laplacian of the frame.
+    pub sharpness: f64,
+    /// Seconds between the acquisition times of the two frames, see
+    /// [`acquisition_time`].
+    pub interval: Option<f64>,
+    pub flags: Vec<Flag>,
+}
+
+fn mean(im: &GrayImage) -> f64 {
+    im.iter().map(|v| *v as f64).sum::<f64>() / im.len().max(1) as f64
+}
+
+/// Variance of the 4-neighbour Laplacian, low for blurred frames.
+fn sharpness(im: &GrayImage) -> f64 {
+    let (width, height) = im.dimensions();
+    if width < 3 || height < 3 {
+        return 0.;
+    }
+    let pix = |x: u32, y: u32| im.get_pixel(x, y)[0] as f64;
+    let values: Vec<f64> = itertools::iproduct!(1..height - 1, 1..width - 1)
+        .map(|(y, x)| {
+            pix(x - 1, y) + pix(x + 1, y) + pix(x, y - 1) + pix(x, y + 1) - 4. * pix(x, y)
+        })
+        .collect();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n
+}
+
+/// Seconds since 1970-01-01 of a date and time `[year, month, day, hour,
+/// minute, second]`.
+fn civil_seconds([year, month, day, hour, minute, second]: [u32; 6]) -> Option<f64> {
+    let valid = (1970..=2200).contains(&year)
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hour < 24
+        && minute < 60
+        && second <= 60;
+    if !valid {
+        return None;
+    }
+    // days from the civil calendar, with years starting in March
+    let (month, day) = (month as i64, day as i64);
+    let year = year as i64 - i64::from(month <= 2);
+    let (era, yoe) = (year.div_euclid(400), year.rem_euclid(400));
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+    Some((days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64) as f64)
+}
+
+/// A time stamp of 14 digits, `YYYYMMDDhhmmss` with any separators between
+/// the groups of digits, e.g. `2024:01:31 23:59:59` or `IMG_20240131_235959`.
+fn parse_timestamp(text: &str) -> Option<f64> {
+    let runs: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|run| !run.is_empty())
+        .collect();
+    (0..runs.len()).find_map(|first| {
+        let mut digits = String::new();
+        for run in &runs[first..] {
+            digits.push_str(run);
+            if digits.len() >= 14 {
+                break;
+            }
+        }
+        if digits.len() != 14 {
+            return None;
+        }
+        let field = |range: std::ops::Range<usize>| digits[range].parse::<u32>().ok();
+        civil_seconds([
+            field(0..4)?,
+            field(4..6)?,
+            field(6..8)?,
+            field(8..10)?,
+            field(10..12)?,
+            field(12..14)?,
+        ])
+    })
+}
+
+/// `DateTimeOriginal` of an EXIF block, or `DateTime` without it, with
+/// the sub-seconds when they are recorded.
+fn exif_time(exif: &[u8]) -> Option<f64> {
+    let little = match exif.get(..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let b = exif.get(at..at.checked_add(2)?)?;
+        Some(if little {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    };
+    let u32_at = |at: usize| {
+        let b = exif.get(at..at.checked_add(4)?)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        } as usize)
+    };
+    // (tag, type, count, offset of the value) of the entries of an IFD
+    let entries = |ifd: usize| -> Option<Vec<(u16, u16, usize, usize)>> {
+        (0..u16_at(ifd)? as usize)
+            .map(|k| {
+                let at = ifd + 2 + 12 * k;
+                Some((u16_at(at)?, u16_at(at + 2)?, u32_at(at + 4)?, at + 8))
+            })
+            .collect()
+    };
+    let ascii = |entries: &[(u16, u16, usize, usize)], tag: u16| {
+        let &(_, _, count, at) = entries.iter().find(|e| e.0 == tag && e.1 == 2)?;
+        let start = if count > 4 { u32_at(at)? } else { at };
+        let bytes = exif.get(start..start.checked_add(count)?)?;
+        Some(
+            String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .trim()
+                .to_owned(),
+        )
+    };
+
+    let ifd0 = entries(u32_at(4)?)?;
+    let exif_ifd = ifd0
+        .iter()
+        .find(|e| e.0 == 0x8769)
+        .and_then(|e| entries(u32_at(e.3)?))
+        .unwrap_or_default();
+    let (time, subsec) = match ascii(&exif_ifd, 0x9003) {
+        Some(time) => (time, ascii(&exif_ifd, 0x9291)),
+        None => (ascii(&ifd0, 0x0132)?, ascii(&exif_ifd, 0x9290)),
+    };
+    let fraction = subsec
+        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|s| format!("0.{s}").parse::<f64>().ok())
+        .unwrap_or(0.);
+    Some(parse_timestamp(&time)? + fraction)
+}
+
+/// Acquisition time of a frame in seconds, from the EXIF date of the file,
+/// or else a time stamp in its name; `None` without either, since the
+/// modification times do not survive copies.
+pub fn acquisition_time(path: &Path) -> Option<f64> {
+    let exif = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .ok()
+        .and_then(|reader| {
+            use image::ImageDecoder;
+            reader.into_decoder().ok()?.exif_metadata().ok()?
+        });
+    exif.and_then(|exif| exif_time(&exif))
+        .or_else(|| parse_timestamp(&path.file_stem()?.to_string_lossy()))
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+impl FrameQc {
+    /// Metrics of a pair of frames read by [`process::imread`]; the active
+    /// fraction, interval and flags are filled in later.
+    pub fn measure(previous: usize, frame: usize, im1: &GrayImage, im2: &GrayImage) -> Self {
+        let (mean1, mean2) = (mean(im1), mean(im2));
+        let delta = process::delta(im1, im2);
+        Self {
+            frame,
+            previous,
+            mean_intensity: mean2,
+            intensity_change: mean2 - mean1,
+            delta_std: Noise::estimate(&delta, Normalization::Std).sigma,
+            sharpness: sharpness(im2),
+            ..Default::default()
+        }
+    }
+
+    /// Flag the outliers of a run. An exposure jump is a change of the mean
+    /// intensity above 5 robust std of the changes of the run, and at least
+    /// one gray level; a frame is blurred below half the median sharpness,
+    /// and frames are dropped when the interval is 1.5 times the median
+    /// interval per frame, when the frames have acquisition times.
+    pub fn flag(qcs: &mut [Self], images: &[PathBuf]) {
+        let mut times = std::collections::HashMap::new();
+        for qc in qcs.iter_mut() {
+            let mut time = |idx: usize| {
+                *times
+                    .entry(idx)
+                    .or_insert_with(|| acquisition_time(&images[idx]))
+            };
+            qc.interval = time(qc.frame)
+                .zip(time(qc.previous))
+                .map(|(t2, t1)| t2 - t1);
+        }
+
+        let changes: Vec<f64> = qcs.iter().map(|qc| qc.intensity_change.abs()).collect();
+        let jump = median(changes.clone()).map(|center| {
+            let mad = median(changes.iter().map(|v| (v - center).abs()).collect());
+            (center + 5. * 1.4826 * mad.unwrap_or(0.)).max(1.)
+        });
+        let blurred = median(qcs.iter().map(|qc| qc.sharpness).collect()).map(|v| 0.5 * v);
+        let per_frame = median(
+            qcs.iter()
+                .filter_map(|qc| Some(qc.interval? / qc.frame.abs_diff(qc.previous).max(1) as f64))
+                .collect(),
+        );
+
+        for qc in qcs.iter_mut() {
+            qc.flags.clear();
+            if jump.is_some_and(|jump| qc.intensity_change.abs() > jump) {
+                qc.flags.push(Flag::ExposureJump);
+            }
+            // every difference is zero
+            if qc.delta_std == 0. && qc.intensity_change == 0. {
+                qc.flags.push(Flag::Duplicate);
+            }
+            let expected = per_frame.map(|t| 1.5 * t * qc.frame.abs_diff(qc.previous) as f64);
+            if let (Some(interval), Some(expected)) = (qc.interval, expected) {
+                if expected > 0. && interval > expected {
+                    qc.flags.push(Flag::Dropped);
+                }
+            }
+            if blurred.is_some_and(|blurred| qc.sharpness < blurred) {
+                qc.flags.push(Flag::Blurred);
+            }
+        }
+    }
+
+    /// Flags as written in `QC.csv`, e.g. `duplicate; blurred`.
+    pub fn flag_text(&self) -> String {
+        self.flags
+            .iter()
+            .map(|flag| flag.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// One row per pair (`previous`, `current`) with its metrics and flags, in
+/// the order of `pairs`; pairs without metrics, e.g. excluded ones, are `NA`.
+pub fn write_csv<P: AsRef<Path>>(
+    path: P,
+    pairs: &[(usize, usize)],
+    qcs: &[FrameQc],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "frame",
+        "previous",
+        "mean_intensity",
+        "intensity_change",
+        "delta_std",
+        "active_fraction",
+        "sharpness",
+        "interval_s",
+        "flags",
+    ])?;
+    let mut qcs = qcs.iter().peekable();
+    for &(previous, frame) in pairs {
+        let Some(qc) = qcs.next_if(|qc| (qc.previous, qc.frame) == (previous, frame)) else {
+            let mut record = vec![frame.to_string(), previous.to_string()];
+            record.extend([NA; 6].map(str::to_owned));
+            record.push(String::new());
+            writer.write_record(&record)?;
+            continue;
+        };
+        writer.write_record([
+            qc.frame.to_string(),
+            qc.previous.to_string(),
+            format!("{:.3}", qc.mean_intensity),
+            format!("{:.3}", qc.intensity_change),
+            format!("{:.3}", qc.delta_std),
+            format!("{:.3}", qc.active_fraction),
+            format!("{:.3}", qc.sharpness),
+            qc.interval.map_or(String::new(), |t| format!("{t:.3}")),
+            qc.flag_text(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_qc() {
+        // a checkerboard, a blurred copy and a brighter copy
+        let sharp = GrayImage::from_fn(16, 16, |x, y| {
+            Luma([if (x + y) % 2 == 0 { 90 } else { 110 }])
+        });
+        let flat = GrayImage::from_pixel(16, 16, Luma([100]));
+        let bright = GrayImage::from_fn(16, 16, |x, y| Luma([sharp.get_pixel(x, y)[0] + 60]));
+
+        let mut qcs: Vec<FrameQc> = (1..10)
+            .map(|k| FrameQc::measure(k - 1, k, &sharp, &sharp))
+            .collect();
+        qcs.iter_mut().for_each(|qc| qc.delta_std = 1.);
+        qcs[2] = FrameQc::measure(2, 3, &sharp, &flat);
+        qcs[5] = FrameQc::measure(5, 6, &sharp, &bright);
+        qcs[7] = FrameQc::measure(7, 8, &sharp, &sharp);
+
+        let images: Vec<PathBuf> = (0..10)
+            .map(|k| PathBuf::from(format!("/missing/{k}.png")))
+            .collect();
+        FrameQc::flag(&mut qcs, &images);
+        assert_eq!(qcs[2].flags, [Flag::Blurred]);
+        assert_eq!(qcs[5].flags, [Flag::ExposureJump]);
+        assert_eq!(qcs[7].flags, [Flag::Duplicate]);
+        assert!(qcs[0].flags.is_empty());
+        assert_eq!(qcs[0].interval, None);
+
+        // the pair (9, 10) is excluded and written as NA
+        let path = std::env::temp_dir().join(format!("qc-{}.csv", std::process::id()));
+        let pairs: Vec<(usize, usize)> = (1..11).map(|k| (k - 1, k)).collect();
+        write_csv(&path, &pairs, &qcs).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents.lines().count(), 11);
+        assert_eq!(contents.lines().last(), Some("10,9,NA,NA,NA,NA,NA,NA,"));
+    }
+
+    #[test]
+    fn test_acquisition_time() {
+        let t0 = parse_timestamp("IMG_20240131_235959").unwrap();
+        assert_eq!(parse_timestamp("2024-02-01 00.00.09_0001"), Some(t0 + 10.));
+        assert_eq!(t0, 1706745599.);
+        assert_eq!(parse_timestamp("frame000123"), None);
+        assert_eq!(parse_timestamp("20241331_000000"), None);
+
+        // little-endian TIFF with an Exif IFD holding DateTimeOriginal and
+        // SubSecTimeOriginal
+        let mut exif = b"II*\0".to_vec();
+        exif.extend(8u32.to_le_bytes());
+        let entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            [
+                &tag.to_le_bytes()[..],
+                &kind.to_le_bytes(),
+                &count.to_le_bytes(),
+                &value,
+            ]
+            .concat()
+        };
+        exif.extend(1u16.to_le_bytes());
+        exif.extend(entry(0x8769, 4, 1, 26u32.to_le_bytes()));
+        exif.extend(0u32.to_le_bytes());
+        exif.extend(2u16.to_le_bytes());
+        exif.extend(entry(0x9003, 2, 20, 56u32.to_le_bytes()));
+        exif.extend(entry(0x9291, 2, 3, *b"25\0\0"));
+        exif.extend(0u32.to_le_bytes());
+        exif.extend(b"2024:01:31 23:59:59\0");
+        assert_eq!(exif_time(&exif), Some(t0 + 0.25));
+        assert_eq!(exif_time(&exif[..40]), None);
+
+        // one frame every 10 s, and 40 s before frame 5
+        let images: Vec<PathBuf> = (0..10)
+            .map(|k| {
+                let t = if k < 5 { 10 * k } else { 10 * k + 30 };
+                PathBuf::from(format!(
+                    "/missing/plate_20240131_12{:02}{:02}.png",
+                    t / 60,
+                    t % 60
+                ))
+            })
+            .collect();
+        let mut qcs: Vec<FrameQc> = (1..10)
+            .map(|k| FrameQc {
+                previous: k - 1,
+                frame: k,
+                delta_std: 1.,
+                ..Default::default()
+            })
+            .collect();
+        FrameQc::flag(&mut qcs, &images);
+        assert_eq!(qcs[0].interval, Some(10.));
+        assert_eq!(qcs[4].flags, [Flag::Dropped]);
+        assert_eq!(qcs.iter().filter(|qc| !qc.flags.is_empty()).count(), 1);
+    }
+}
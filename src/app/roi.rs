@@ -1,20 +1,121 @@
-use std::error::Error;
-
 use super::font::ROBOTO_FNT;
-use image::{GenericImageView, ImageBuffer};
-use imageproc::drawing::{draw_hollow_rect_mut, draw_text_mut};
-use itertools::iproduct;
+use super::plate::{normalize_well, well_name, Numbering, PlateFormat, WellInfo};
+use image::ImageBuffer;
+use imageproc::drawing::{
+    draw_hollow_ellipse_mut, draw_hollow_polygon_mut, draw_hollow_rect_mut, draw_line_segment_mut,
+    draw_text_mut,
+};
+use itertools::{iproduct, Itertools};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// ROIs and image size that warnings were found for, with the warnings.
+type Checked = (Arc<[Roi]>, u32, u32, Vec<RoiWarning>);
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
-struct Roi {
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-    index: usize,
+#[serde(deny_unknown_fields)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub index: usize,
+    #[serde(default)]
+    pub row: u32,
+    #[serde(default)]
+    pub col: u32,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub excluded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<WellInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Shape::is_rectangle")]
+    pub shape: Shape,
+    /// Binarization threshold of this ROI instead of the global one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+}
+
+/// Outline of a ROI inside its bounding box.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Shape {
+    #[default]
+    Rectangle,
+    Oval,
+    /// Vertices in image coordinates.
+    Polygon {
+        points: Vec<[i32; 2]>,
+    },
+}
+
+impl Shape {
+    pub fn is_rectangle(&self) -> bool {
+        *self == Shape::Rectangle
+    }
+}
+
+/// ROI placed freely instead of on the grid, e.g. imported from ImageJ.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FreeRoi {
+    #[serde(default)]
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Shape::is_rectangle")]
+    pub shape: Shape,
+}
+
+/// Manual displacement of a single ROI relative to its grid position.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RoiOffset {
+    pub row: u32,
+    pub col: u32,
+    pub dx: i32,
+    pub dy: i32,
+}
+
+/// Group of a single ROI, by its grid position.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RoiGroup {
+    pub row: u32,
+    pub col: u32,
+    pub group: String,
+}
+
+/// Binarization threshold of a single ROI, overriding the global one.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RoiThreshold {
+    pub row: u32,
+    pub col: u32,
+    pub threshold: f64,
+}
+
+/// Grid spanned by explicit step vectors between neighbouring ROIs, which
+/// covers shear and unequal spacing caused by the camera perspective.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Affine {
+    pub origin: [f64; 2],
+    pub col: [f64; 2],
+    pub row: [f64; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    #[default]
+    Rotation,
+    Affine,
 }
 
 impl Roi {
@@ -24,44 +125,164 @@ impl Roi {
             y,
             width,
             height,
-            index: 0,
+            ..Default::default()
         }
     }
     fn set_index(mut self, idx: usize) -> Self {
         self.index = idx;
         self
     }
+    fn set_grid(mut self, row: u32, col: u32) -> Self {
+        self.row = row;
+        self.col = col;
+        self
+    }
+    fn set_label(mut self, label: String) -> Self {
+        self.label = label;
+        self
+    }
+    fn set_excluded(mut self, excluded: bool) -> Self {
+        self.excluded = excluded;
+        self
+    }
+    fn set_info(mut self, info: Option<WellInfo>) -> Self {
+        self.info = info;
+        self
+    }
+    fn set_group(mut self, group: Option<String>) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn contains(&self, px: f64, py: f64) -> bool {
+        px >= self.x as f64
+            && py >= self.y as f64
+            && px < (self.x + self.width) as f64
+            && py < (self.y + self.height) as f64
+    }
+    fn set_shape(mut self, shape: Shape) -> Self {
+        self.shape = shape;
+        self
+    }
+    fn set_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Whether the center of the pixel at (`px`, `py`) lies inside the ROI outline.
+    pub fn covers(&self, px: u32, py: u32) -> bool {
+        let (cx, cy) = (px as f64 + 0.5, py as f64 + 0.5);
+        match &self.shape {
+            Shape::Rectangle => true,
+            Shape::Oval => {
+                let rx = self.width as f64 / 2.;
+                let ry = self.height as f64 / 2.;
+                let dx = (cx - self.x as f64 - rx) / rx;
+                let dy = (cy - self.y as f64 - ry) / ry;
+                dx * dx + dy * dy <= 1.
+            }
+            Shape::Polygon { points } => {
+                // even-odd rule
+                let mut inside = false;
+                for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                    let (ax, ay) = (a[0] as f64, a[1] as f64);
+                    let (bx, by) = (b[0] as f64, b[1] as f64);
+                    if (ay > cy) != (by > cy) && cx < ax + (cy - ay) / (by - ay) * (bx - ax) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    /// Number of pixels inside the outline that also lie within a
+    /// `width` x `height` image.
+    pub fn pixel_area(&self, width: u32, height: u32) -> u32 {
+        self.pixels(width, height).count() as u32
+    }
+
+    /// Pixels (`x`, `y`) inside the outline and within a `width` x `height`
+    /// image, row by row.
+    pub fn pixels(&self, width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let x2 = (self.x + self.width).min(width);
+        let y2 = (self.y + self.height).min(height);
+        iproduct!(self.y..y2, self.x..x2)
+            .map(|(py, px)| (px, py))
+            .filter(|&(px, py)| self.covers(px, py))
+    }
+
+    fn overlaps(&self, other: &Roi) -> bool {
+        let (x1, x2) = (
+            self.x.max(other.x),
+            (self.x + self.width).min(other.x + other.width),
+        );
+        let (y1, y2) = (
+            self.y.max(other.y),
+            (self.y + self.height).min(other.y + other.height),
+        );
+        iproduct!(y1..y2, x1..x2).any(|(py, px)| self.covers(px, py) && other.covers(px, py))
+    }
+
+    /// Moving pixels of a mask inside the outline; 0 for a ROI outside the
+    /// image.
     fn measure(&self, im: &ImageBuffer<image::Luma<u8>, Vec<u8>>) -> u32 {
-        let x2 = std::cmp::min(self.width + self.x, im.width());
-        let y2 = std::cmp::min(self.height + self.y, im.height());
-        let width = x2.saturating_sub(self.x);
-        let height = y2.saturating_sub(self.y);
-        im.view(self.x, self.y, width, height)
-            .pixels()
-            .fold(0u32, |acc, pix| {
-                let (_, _, image::Luma([pix])) = pix;
-                acc + (pix as u32 & 1)
-            })
+        self.pixels(im.width(), im.height())
+            .map(|(px, py)| im.get_pixel(px, py)[0] as u32 & 1)
+            .sum()
     }
 
     fn draw_roi(
         &self,
         gray: &mut ImageBuffer<image::Rgba<u8>, Vec<u8>>,
         font: &impl ab_glyph::Font,
+        color: image::Rgba<u8>,
     ) {
-        let white = image::Rgba([255, 255, 0, 128]);
-        draw_hollow_rect_mut(
-            gray,
-            imageproc::rect::Rect::at(self.x as i32, self.y as i32)
-                .of_size(self.width, self.height),
-            white,
-        );
-        draw_hollow_rect_mut(
-            gray,
-            imageproc::rect::Rect::at(self.x as i32 - 1, self.y as i32 - 1)
-                .of_size(self.width + 2, self.height + 2),
-            white,
-        );
+        let white = if self.excluded {
+            image::Rgba([128, 128, 128, 128])
+        } else {
+            color
+        };
+        if self.excluded {
+            let (x1, y1) = (self.x as f32, self.y as f32);
+            let (x2, y2) = (x1 + self.width as f32, y1 + self.height as f32);
+            draw_line_segment_mut(gray, (x1, y1), (x2, y2), white);
+            draw_line_segment_mut(gray, (x1, y2), (x2, y1), white);
+        }
+        match &self.shape {
+            Shape::Rectangle => {
+                draw_hollow_rect_mut(
+                    gray,
+                    imageproc::rect::Rect::at(self.x as i32, self.y as i32)
+                        .of_size(self.width, self.height),
+                    white,
+                );
+                draw_hollow_rect_mut(
+                    gray,
+                    imageproc::rect::Rect::at(self.x as i32 - 1, self.y as i32 - 1)
+                        .of_size(self.width + 2, self.height + 2),
+                    white,
+                );
+            }
+            Shape::Oval => {
+                let center = (
+                    (self.x + self.width / 2) as i32,
+                    (self.y + self.height / 2) as i32,
+                );
+                let (rx, ry) = ((self.width / 2) as i32, (self.height / 2) as i32);
+                draw_hollow_ellipse_mut(gray, center, rx, ry, white);
+                draw_hollow_ellipse_mut(gray, center, rx + 1, ry + 1, white);
+            }
+            Shape::Polygon { points } => {
+                let poly: Vec<imageproc::point::Point<f32>> = points
+                    .iter()
+                    .map(|p| imageproc::point::Point::new(p[0] as f32, p[1] as f32))
+                    .collect();
+                if poly.len() > 1 {
+                    draw_hollow_polygon_mut(gray, &poly, white);
+                }
+            }
+        }
         draw_text_mut(
             gray,
             white,
@@ -69,28 +290,218 @@ impl Roi {
             self.y as i32 - 15,
             16.,
             &font,
-            &format!("{}", self.index),
+            &self.label,
         )
     }
 }
+
+/// A collection name as it appears in the file names of the outputs, with
+/// the characters that cannot be used in a file name replaced by `_`.
+pub fn file_stem(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RoiCollection {
+    /// Name of the collection when a dataset has several, e.g. one per plate.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
     pub nrow: u32,
     pub ncol: u32,
-    pub x: u32,
-    pub y: u32,
+    pub x: i32,
+    pub y: i32,
     pub xinterval: u32,
     pub yinterval: u32,
     pub width: u32,
     pub height: u32,
     pub rotate: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offsets: Vec<RoiOffset>,
+    /// Per-ROI binarization thresholds.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thresholds: Vec<RoiThreshold>,
+    /// Freely placed ROIs; when present they replace the grid.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<FreeRoi>,
+    /// Metadata of each well from an imported plate map, keyed by well name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plate_map: BTreeMap<String, WellInfo>,
+    /// Groups of single ROIs; take precedence over the plate map.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grouping: Vec<RoiGroup>,
+    /// Grid positions (`[row, col]`) of the ROIs left out of the measurement.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<[u32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affine: Option<Affine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plate: Option<PlateFormat>,
+    #[serde(default)]
+    pub numbering: Numbering,
     #[serde(skip_serializing_if = "Option::is_none")]
     rois: Option<Arc<[Roi]>>,
+    /// Last result of [`RoiCollection::check`], redrawn on every repaint.
+    #[serde(skip)]
+    checked: Arc<Mutex<Option<Checked>>>,
+}
+
+/// Which tail of the normalized difference (first frame minus second frame
+/// of a pair) counts as motion.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// Pixels darker in the first frame, as in imagesubtractor.
+    #[default]
+    Darker,
+    /// Pixels brighter in the first frame.
+    Brighter,
+    /// Pixels changed either way.
+    Absolute,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Darker => write!(f, "Darker"),
+            Direction::Brighter => write!(f, "Brighter"),
+            Direction::Absolute => write!(f, "Absolute"),
+        }
+    }
+}
+
+/// Gray levels at and beyond which a pixel is moving, below and above the
+/// mean, for `threshold` times std.
+fn cuts(threshold: f64) -> (u8, u8) {
+    let low = (127.0f64 - threshold * 12.8f64).clamp(0f64, 255f64).round() as u8;
+    // the mean sits at 127.5, so the bright cut mirrors the dark one
+    (low, 255 - low)
+}
+
+fn is_moving(pix: u8, (low, high): (u8, u8), direction: Direction) -> bool {
+    match direction {
+        Direction::Darker => pix <= low,
+        Direction::Brighter => pix >= high,
+        Direction::Absolute => pix <= low || pix >= high,
+    }
+}
+
+/// Moving pixels (255) of a normalized difference image, `threshold` times
+/// std away from the mean in the given `direction`.
+pub fn binarize(
+    subimg: &ImageBuffer<image::Luma<u8>, Vec<u8>>,
+    threshold: f64,
+    direction: Direction,
+) -> ImageBuffer<image::Luma<u8>, Vec<u8>> {
+    let cuts = cuts(threshold);
+    let mut mask = subimg.clone();
+    mask.iter_mut().for_each(|pix| {
+        *pix = if is_moving(*pix, cuts, direction) {
+            255
+        } else {
+            0
+        };
+    });
+    mask
+}
+
+/// Pixels of a `width` x `height` image outside every ROI of `collections`,
+/// excluded ROIs included.
+pub fn background_mask<'a>(
+    collections: impl IntoIterator<Item = &'a RoiCollection>,
+    width: u32,
+    height: u32,
+) -> Vec<bool> {
+    let mut background = vec![true; (width * height) as usize];
+    for roi in collections.into_iter().flat_map(|roicol| roicol.rois()) {
+        roi.pixels(width, height)
+            .for_each(|(px, py)| background[(py * width + px) as usize] = false);
+    }
+    background
+}
+
+/// Pixel indices of every ROI of `collections` in a `width` x `height`
+/// image, for statistics computed per ROI.
+pub fn roi_regions<'a>(
+    collections: impl IntoIterator<Item = &'a RoiCollection>,
+    width: u32,
+    height: u32,
+) -> Vec<Vec<u32>> {
+    collections
+        .into_iter()
+        .flat_map(|roicol| roicol.rois())
+        .map(|roi| {
+            roi.pixels(width, height)
+                .map(|(px, py)| py * width + px)
+                .collect()
+        })
+        .collect()
+}
+
+/// Problem found by [`RoiCollection::check`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoiIssue {
+    /// No pixel of the ROI lies inside the image.
+    Empty,
+    /// The ROI is clipped by the image border, only `effective` of its
+    /// `area` pixels are measured.
+    OutOfBounds { area: u32, effective: u32 },
+    /// Pixels are counted in both this ROI and the one at `row`, `col`.
+    Overlap { row: u32, col: u32, label: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoiWarning {
+    pub row: u32,
+    pub col: u32,
+    pub label: String,
+    pub issue: RoiIssue,
+}
+
+impl std::fmt::Display for RoiWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.issue {
+            RoiIssue::Empty => write!(f, "ROI {}: no pixel inside the image", self.label),
+            RoiIssue::OutOfBounds { area, effective } => write!(
+                f,
+                "ROI {}: clipped by the image border, {effective} of {area} px measured",
+                self.label
+            ),
+            RoiIssue::Overlap { label, .. } => {
+                write!(f, "ROI {}: overlaps ROI {label}", self.label)
+            }
+        }
+    }
+}
+
+/// Overlay color of the `idx`-th ROI group.
+pub fn group_color(idx: usize) -> image::Rgba<u8> {
+    const PALETTE: [[u8; 3]; 8] = [
+        [255, 255, 0],
+        [0, 255, 255],
+        [255, 0, 255],
+        [0, 255, 0],
+        [255, 128, 0],
+        [64, 160, 255],
+        [255, 64, 64],
+        [255, 255, 255],
+    ];
+    let [r, g, b] = PALETTE[idx % PALETTE.len()];
+    image::Rgba([r, g, b, 128])
 }
 
 impl std::default::Default for RoiCollection {
     fn default() -> Self {
         Self {
+            name: String::new(),
             nrow: 6,
             ncol: 8,
             x: 18,
@@ -100,87 +511,839 @@ impl std::default::Default for RoiCollection {
             width: 78,
             height: 78,
             rotate: 0.0,
+            offsets: Vec::new(),
+            thresholds: Vec::new(),
+            custom: Vec::new(),
+            plate_map: BTreeMap::new(),
+            grouping: Vec::new(),
+            excluded: Vec::new(),
+            affine: None,
+            plate: None,
+            numbering: Numbering::default(),
             rois: None,
+            checked: Default::default(),
         }
     }
 }
 
 impl RoiCollection {
-    pub fn measure_all(
-        &self,
-        subimg: &ImageBuffer<image::Luma<u8>, Vec<u8>>,
-        threshold: f64,
-    ) -> Option<Vec<u32>> {
-        let thresh = (127.0f64 - threshold * 12.8f64).clamp(0f64, 255f64).round() as u8;
-        let thres_im = imageproc::contrast::threshold(
-            subimg,
-            thresh,
-            imageproc::contrast::ThresholdType::BinaryInverted,
-        );
-        // invert the byte;
-        // thres_im.iter_mut().for_each(|pix| {
-        //     *pix = !*pix;
-        // });
-
-        self.rois
-            .as_ref()
-            .map(|rois| rois.iter().map(|roi| roi.measure(&thres_im)).collect())
+    /// Moving pixels of every measured ROI in a binarized image.
+    pub fn measure_mask(&self, mask: &ImageBuffer<image::Luma<u8>, Vec<u8>>) -> Option<Vec<u32>> {
+        self.rois.as_ref().map(|rois| {
+            rois.iter()
+                .filter(|roi| !roi.excluded)
+                .map(|roi| roi.measure(mask))
+                .collect()
+        })
     }
 
     pub fn update_rois(&mut self) {
-        let rot = self.rotate / 180. * std::f64::consts::PI;
-        let rot_cos = rot.cos();
-        let rot_sin = rot.sin();
-
+        if !self.custom.is_empty() {
+            self.update_custom_rois();
+            return;
+        }
         let rois = iproduct!(0..self.nrow, 0..self.ncol)
             .map(|(i, j)| {
-                let fx = (self.x + j * self.xinterval) as f64;
-                let fy = (self.y + i * self.yinterval) as f64;
-                let fx = fx * rot_cos - fy * rot_sin;
-                let fy = fx * rot_sin + fy * rot_cos;
+                let (fx, fy) = self.grid_point(i, j);
+                let (dx, dy) = self
+                    .offset(i, j)
+                    .map(|o| (o.dx as f64, o.dy as f64))
+                    .unwrap_or_default();
 
                 Roi::new(
-                    f64::max(fx.round(), 0.) as u32,
-                    f64::max(fy.round(), 0.) as u32,
+                    f64::max((fx + dx).round(), 0.) as u32,
+                    f64::max((fy + dy).round(), 0.) as u32,
                     self.width,
                     self.height,
                 )
+                .set_grid(i, j)
+            })
+            .map(|roi| {
+                let idx = self.numbering.index(roi.row, roi.col, self.nrow, self.ncol);
+                let label = self.numbering.label(roi.row, roi.col, idx);
+                let excluded = self.is_excluded(roi.row, roi.col);
+                let info = self
+                    .plate_map
+                    .get(&well_name(roi.row, roi.col))
+                    .or_else(|| self.plate_map.get(&normalize_well(&label)))
+                    .cloned();
+                let group = self.group(roi.row, roi.col).map(str::to_owned).or_else(|| {
+                    info.as_ref()
+                        .map(WellInfo::group)
+                        .filter(|group| !group.is_empty())
+                });
+                let threshold = self.threshold(roi.row, roi.col);
+                roi.set_index(idx)
+                    .set_label(label)
+                    .set_excluded(excluded)
+                    .set_info(info)
+                    .set_group(group)
+                    .set_threshold(threshold)
             })
-            .enumerate()
-            .map(|(idx, roi)| roi.set_index(idx))
+            .sorted_by_key(|roi| roi.index)
             .collect::<Arc<[Roi]>>();
         self.rois = Some(rois);
     }
 
-    pub fn draw_rois(&self, gray: &mut ImageBuffer<image::Rgba<u8>, Vec<u8>>) {
-        let font = ab_glyph::FontArc::try_from_slice(ROBOTO_FNT.as_ref()).unwrap();
+    /// Build the ROIs from [`RoiCollection::custom`]; they are addressed as
+    /// row 0, column `k` by offsets and exclusions.
+    fn update_custom_rois(&mut self) {
+        let rois = self
+            .custom
+            .iter()
+            .zip(0u32..)
+            .map(|(free, k)| {
+                let (dx, dy) = self.offset(0, k).map(|o| (o.dx, o.dy)).unwrap_or_default();
+                let (x, y) = (free.x + dx, free.y + dy);
+                let shape = match &free.shape {
+                    Shape::Polygon { points } => Shape::Polygon {
+                        points: points.iter().map(|p| [p[0] + dx, p[1] + dy]).collect(),
+                    },
+                    shape => shape.clone(),
+                };
+                let idx = k as usize + self.numbering.start;
+                let label = if free.name.is_empty() {
+                    idx.to_string()
+                } else {
+                    free.name.clone()
+                };
+                let group = self.group(0, k).map(str::to_owned);
+                Roi::new(x.max(0) as u32, y.max(0) as u32, free.width, free.height)
+                    .set_grid(0, k)
+                    .set_index(idx)
+                    .set_label(label)
+                    .set_excluded(self.is_excluded(0, k))
+                    .set_group(group)
+                    .set_shape(shape)
+                    .set_threshold(self.threshold(0, k))
+            })
+            .collect::<Arc<[Roi]>>();
+        self.rois = Some(rois);
+    }
 
-        if let Some(rois) = self.rois.as_ref() {
-            rois.iter().for_each(|roi| {
-                roi.draw_roi(gray, &font);
+    /// Switch to a standard plate layout, spreading the new rows and columns
+    /// over the area covered by the current grid.
+    pub fn apply_template(&mut self, plate: PlateFormat) {
+        let (nrow, ncol) = plate.shape();
+        let rescale = |interval: u32, from: u32, to: u32| {
+            if from > 1 && to > 1 {
+                (interval as f64 * (from - 1) as f64 / (to - 1) as f64).round() as u32
+            } else {
+                interval
+            }
+        };
+        let xinterval = rescale(self.xinterval, self.ncol, ncol);
+        let yinterval = rescale(self.yinterval, self.nrow, nrow);
+        if self.xinterval > 0 {
+            self.width =
+                (self.width as f64 * xinterval as f64 / self.xinterval as f64).round() as u32;
+        }
+        if self.yinterval > 0 {
+            self.height =
+                (self.height as f64 * yinterval as f64 / self.yinterval as f64).round() as u32;
+        }
+        if let Some(affine) = self.affine.as_mut() {
+            let (sx, sy) = (
+                xinterval as f64 / self.xinterval.max(1) as f64,
+                yinterval as f64 / self.yinterval.max(1) as f64,
+            );
+            affine.col = [affine.col[0] * sx, affine.col[1] * sx];
+            affine.row = [affine.row[0] * sy, affine.row[1] * sy];
+        }
+        self.xinterval = xinterval;
+        self.yinterval = yinterval;
+        self.nrow = nrow;
+        self.ncol = ncol;
+        self.plate = Some(plate);
+        self.numbering.well_names = true;
+        // per-well settings no longer match the wells of the new grid
+        self.offsets.clear();
+        self.thresholds.clear();
+        self.excluded.clear();
+        self.grouping.clear();
+    }
+
+    /// Sanity checks of the measured ROIs against a `width` x `height` image,
+    /// found again only when the ROIs or the size change.
+    pub fn check(&self, width: u32, height: u32) -> Vec<RoiWarning> {
+        let Some(rois) = self.rois.as_ref() else {
+            return vec![];
+        };
+        let mut checked = self.checked.lock().expect("poisoned ROI check");
+        match checked.as_ref() {
+            Some((cached, w, h, warnings))
+                if Arc::ptr_eq(cached, rois) && (*w, *h) == (width, height) =>
+            {
+                warnings.clone()
+            }
+            _ => {
+                let warnings = self.find_warnings(width, height);
+                *checked = Some((Arc::clone(rois), width, height, warnings.clone()));
+                warnings
+            }
+        }
+    }
+
+    fn find_warnings(&self, width: u32, height: u32) -> Vec<RoiWarning> {
+        let rois: Vec<&Roi> = self.measured().collect();
+        let mut warnings = vec![];
+        let mut warn = |roi: &Roi, issue| {
+            warnings.push(RoiWarning {
+                row: roi.row,
+                col: roi.col,
+                label: roi.label.clone(),
+                issue,
             })
         };
+        for (i, roi) in rois.iter().enumerate() {
+            let area = roi.pixel_area(u32::MAX, u32::MAX);
+            let effective = roi.pixel_area(width, height);
+            if effective == 0 {
+                warn(roi, RoiIssue::Empty);
+            } else if effective < area {
+                warn(roi, RoiIssue::OutOfBounds { area, effective });
+            }
+            for other in rois[i + 1..].iter().filter(|other| roi.overlaps(other)) {
+                let issue = RoiIssue::Overlap {
+                    row: other.row,
+                    col: other.col,
+                    label: other.label.clone(),
+                };
+                warn(roi, issue);
+            }
+        }
+        warnings
+    }
+
+    /// ROIs that are measured, in numbering order.
+    pub fn measured(&self) -> impl Iterator<Item = &Roi> {
+        self.rois().iter().filter(|roi| !roi.excluded)
+    }
+
+    /// Column headers of the outputs, one per measured ROI in numbering order.
+    pub fn labels(&self) -> Vec<String> {
+        self.measured().map(|roi| roi.label.clone()).collect()
+    }
+
+    /// Distinct ROI groups in sorted order.
+    pub fn groups(&self) -> Vec<String> {
+        self.rois()
+            .iter()
+            .filter_map(|roi| roi.group.clone())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    pub fn is_excluded(&self, row: u32, col: u32) -> bool {
+        self.excluded.contains(&[row, col])
+    }
+
+    pub fn set_excluded(&mut self, row: u32, col: u32, excluded: bool) {
+        self.excluded.retain(|pos| *pos != [row, col]);
+        if excluded {
+            self.excluded.push([row, col]);
+            self.excluded.sort_unstable();
+        }
+    }
+
+    fn rotation(&self) -> (f64, f64) {
+        let rot = self.rotate.to_radians();
+        (rot.cos(), rot.sin())
+    }
+
+    /// Top-left corner of the ROI at grid position (`row`, `col`) in image
+    /// coordinates, before any per-ROI offset is applied.
+    pub fn grid_point(&self, row: u32, col: u32) -> (f64, f64) {
+        if let Some(Affine {
+            origin,
+            col: u,
+            row: v,
+        }) = self.affine
+        {
+            let (i, j) = (row as f64, col as f64);
+            return (
+                origin[0] + j * u[0] + i * v[0],
+                origin[1] + j * u[1] + i * v[1],
+            );
+        }
+        let (rot_cos, rot_sin) = self.rotation();
+        let fx = self.x as f64 + (col * self.xinterval) as f64;
+        let fy = self.y as f64 + (row * self.yinterval) as f64;
+        (fx * rot_cos - fy * rot_sin, fx * rot_sin + fy * rot_cos)
+    }
+
+    /// Map an image coordinate back into the unrotated grid frame.
+    fn unrotate(&self, px: f64, py: f64) -> (f64, f64) {
+        let (rot_cos, rot_sin) = self.rotation();
+        (px * rot_cos + py * rot_sin, -px * rot_sin + py * rot_cos)
+    }
+
+    /// Set the origin of the unrotated grid, which may lie outside the image
+    /// when the grid is rotated.
+    fn set_origin(&mut self, gx: f64, gy: f64) {
+        self.x = gx.round() as i32;
+        self.y = gy.round() as i32;
+    }
+
+    /// Unit vector pointing from one row of the grid to the next.
+    pub fn row_direction(&self) -> (f64, f64) {
+        if let Some(Affine { row, .. }) = self.affine {
+            let norm = row[0].hypot(row[1]);
+            if norm > 0. {
+                return (row[0] / norm, row[1] / norm);
+            }
+        }
+        let (rot_cos, rot_sin) = self.rotation();
+        (-rot_sin, rot_cos)
+    }
+
+    /// Shift the whole grid by (`dx`, `dy`) image pixels.
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        if let Some(affine) = self.affine.as_mut() {
+            affine.origin[0] += dx;
+            affine.origin[1] += dy;
+            return;
+        }
+        let (px, py) = self.grid_point(0, 0);
+        let (gx, gy) = self.unrotate(px + dx, py + dy);
+        self.set_origin(gx, gy);
+    }
+
+    /// Rotate the grid by `degree` while keeping the first ROI in place.
+    pub fn rotate_by(&mut self, degree: f64) {
+        if let Some(affine) = self.affine.as_mut() {
+            let (rot_sin, rot_cos) = degree.to_radians().sin_cos();
+            for v in [&mut affine.col, &mut affine.row] {
+                *v = [
+                    v[0] * rot_cos - v[1] * rot_sin,
+                    v[0] * rot_sin + v[1] * rot_cos,
+                ];
+            }
+            return;
+        }
+        let (px, py) = self.grid_point(0, 0);
+        self.rotate = (self.rotate + degree).clamp(-90., 90.);
+        let (gx, gy) = self.unrotate(px, py);
+        self.set_origin(gx, gy);
+    }
+
+    /// Stretch the intervals so that the last ROI of the grid lands on (`px`, `py`).
+    pub fn stretch_to(&mut self, px: f64, py: f64) {
+        if let Some(affine) = self.affine.as_mut() {
+            // solve `target = a * span_col + b * span_row` and scale both steps
+            let ncol = self.ncol.saturating_sub(1) as f64;
+            let nrow = self.nrow.saturating_sub(1) as f64;
+            let u = [affine.col[0] * ncol, affine.col[1] * ncol];
+            let v = [affine.row[0] * nrow, affine.row[1] * nrow];
+            let (tx, ty) = (px - affine.origin[0], py - affine.origin[1]);
+            let det = u[0] * v[1] - u[1] * v[0];
+            if det.abs() > f64::EPSILON {
+                let a = (tx * v[1] - ty * v[0]) / det;
+                let b = (u[0] * ty - u[1] * tx) / det;
+                affine.col = [affine.col[0] * a, affine.col[1] * a];
+                affine.row = [affine.row[0] * b, affine.row[1] * b];
+            }
+            return;
+        }
+        let (gx, gy) = self.unrotate(px, py);
+        if self.ncol > 1 {
+            let xinterval = (gx - self.x as f64) / (self.ncol - 1) as f64;
+            self.xinterval = f64::max(xinterval.round(), 0.) as u32;
+        }
+        if self.nrow > 1 {
+            let yinterval = (gy - self.y as f64) / (self.nrow - 1) as f64;
+            self.yinterval = f64::max(yinterval.round(), 0.) as u32;
+        }
+    }
+
+    /// Fit the grid to the centers of the top-left, top-right and bottom-left wells.
+    ///
+    /// [`FitMode::Rotation`] estimates origin, intervals and rotation, while
+    /// [`FitMode::Affine`] keeps the exact step vectors spanned by the three wells.
+    pub fn fit_corners(
+        &mut self,
+        top_left: (f64, f64),
+        top_right: (f64, f64),
+        bottom_left: (f64, f64),
+        mode: FitMode,
+    ) {
+        // the clicked points are well centers, the grid stores top-left corners
+        let (hw, hh) = (self.width as f64 / 2., self.height as f64 / 2.);
+        let origin = [top_left.0 - hw, top_left.1 - hh];
+        let ncol = self.ncol.saturating_sub(1).max(1) as f64;
+        let nrow = self.nrow.saturating_sub(1).max(1) as f64;
+        let col = [
+            (top_right.0 - top_left.0) / ncol,
+            (top_right.1 - top_left.1) / ncol,
+        ];
+        let row = [
+            (bottom_left.0 - top_left.0) / nrow,
+            (bottom_left.1 - top_left.1) / nrow,
+        ];
+        match mode {
+            FitMode::Affine => self.affine = Some(Affine { origin, col, row }),
+            FitMode::Rotation => {
+                self.affine = None;
+                let mut angles = vec![];
+                if self.ncol > 1 {
+                    angles.push(col[1].atan2(col[0]));
+                }
+                if self.nrow > 1 {
+                    angles.push((-row[0]).atan2(row[1]));
+                }
+                let angle = if angles.is_empty() {
+                    0.
+                } else {
+                    angles.iter().sum::<f64>() / angles.len() as f64
+                };
+                self.rotate = (angle.to_degrees() * 100.).round() / 100.;
+                self.xinterval = col[0].hypot(col[1]).round() as u32;
+                self.yinterval = row[0].hypot(row[1]).round() as u32;
+                let (gx, gy) = self.unrotate(origin[0], origin[1]);
+                self.set_origin(gx, gy);
+            }
+        }
+    }
+
+    /// Keep the ROIs of a rotated grid saved before the rotation was fixed
+    /// where they were, as an affine grid: the y coordinate used to be
+    /// computed from the already rotated x.
+    pub fn migrate_legacy_rotation(&mut self) {
+        if self.rotate == 0. || self.affine.is_some() || !self.custom.is_empty() {
+            return;
+        }
+        let (rot_cos, rot_sin) = self.rotation();
+        let (x, y) = (self.x as f64, self.y as f64);
+        let (xi, yi) = (self.xinterval as f64, self.yinterval as f64);
+        let x0 = x * rot_cos - y * rot_sin;
+        self.affine = Some(Affine {
+            origin: [x0, x0 * rot_sin + y * rot_cos],
+            col: [xi * rot_cos, xi * rot_cos * rot_sin],
+            row: [-yi * rot_sin, yi * (rot_cos - rot_sin * rot_sin)],
+        });
+    }
+
+    /// Replace an affine grid with the closest rotated rectangular grid.
+    pub fn clear_affine(&mut self) {
+        if self.affine.is_none() {
+            return;
+        }
+        let (hw, hh) = (self.width as f64 / 2., self.height as f64 / 2.);
+        let center = |(x, y): (f64, f64)| (x + hw, y + hh);
+        let top_left = center(self.grid_point(0, 0));
+        let top_right = center(self.grid_point(0, self.ncol.saturating_sub(1)));
+        let bottom_left = center(self.grid_point(self.nrow.saturating_sub(1), 0));
+        self.fit_corners(top_left, top_right, bottom_left, FitMode::Rotation);
+    }
+
+    pub fn offset(&self, row: u32, col: u32) -> Option<&RoiOffset> {
+        self.offsets.iter().find(|o| o.row == row && o.col == col)
+    }
+
+    /// Set the manual offset of a single ROI; a zero offset removes the entry.
+    pub fn set_offset(&mut self, row: u32, col: u32, dx: i32, dy: i32) {
+        self.offsets.retain(|o| o.row != row || o.col != col);
+        if dx != 0 || dy != 0 {
+            self.offsets.push(RoiOffset { row, col, dx, dy });
+        }
+    }
+
+    pub fn group(&self, row: u32, col: u32) -> Option<&str> {
+        self.grouping
+            .iter()
+            .find(|g| g.row == row && g.col == col)
+            .map(|g| g.group.as_str())
+    }
+
+    pub fn threshold(&self, row: u32, col: u32) -> Option<f64> {
+        self.thresholds
+            .iter()
+            .find(|t| t.row == row && t.col == col)
+            .map(|t| t.threshold)
+    }
+
+    /// Set the threshold of a single ROI; `None` falls back to the global one.
+    pub fn set_threshold(&mut self, row: u32, col: u32, threshold: Option<f64>) {
+        self.thresholds.retain(|t| t.row != row || t.col != col);
+        if let Some(threshold) = threshold {
+            self.thresholds.push(RoiThreshold {
+                row,
+                col,
+                threshold,
+            });
+        }
     }
 
-    pub fn to_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
-        let contents = serde_json::to_string_pretty(self)?;
-        std::fs::write(path, contents)?;
-        Ok(())
+    /// Binarize the pixels of the ROIs with their own threshold again, over
+    /// the `mask` of the global one.
+    pub fn apply_thresholds(
+        &self,
+        subimg: &ImageBuffer<image::Luma<u8>, Vec<u8>>,
+        mask: &mut ImageBuffer<image::Luma<u8>, Vec<u8>>,
+        direction: Direction,
+    ) {
+        for roi in self.rois() {
+            let Some(threshold) = roi.threshold else {
+                continue;
+            };
+            let cuts = cuts(threshold);
+            for (px, py) in roi.pixels(subimg.width(), subimg.height()) {
+                let moving = is_moving(subimg.get_pixel(px, py)[0], cuts, direction);
+                mask.put_pixel(px, py, image::Luma([if moving { 255 } else { 0 }]));
+            }
+        }
+    }
+
+    pub fn rois(&self) -> &[Roi] {
+        self.rois.as_deref().unwrap_or_default()
     }
 
-    pub fn len(&self) -> usize {
-        self.rois.as_ref().map(|rois| rois.len()).unwrap_or(0)
+    /// Current ROIs as free-standing shapes, named by their label.
+    pub fn to_free_rois(&self) -> Vec<FreeRoi> {
+        self.rois()
+            .iter()
+            .map(|roi| FreeRoi {
+                name: roi.label.clone(),
+                x: roi.x as i32,
+                y: roi.y as i32,
+                width: roi.width,
+                height: roi.height,
+                shape: roi.shape.clone(),
+            })
+            .collect()
+    }
+
+    /// Grid position of the ROI under the image coordinate (`px`, `py`).
+    pub fn roi_at(&self, px: f64, py: f64) -> Option<(u32, u32)> {
+        self.rois()
+            .iter()
+            .rev()
+            .find(|roi| roi.contains(px, py))
+            .map(|roi| (roi.row, roi.col))
+    }
+
+    pub fn draw_rois(&self, gray: &mut ImageBuffer<image::Rgba<u8>, Vec<u8>>) {
+        let font = ab_glyph::FontArc::try_from_slice(ROBOTO_FNT.as_ref()).unwrap();
+
+        let groups = self.groups();
+        // highlight both ROIs of an overlap
+        let flagged: Vec<[u32; 2]> = self
+            .check(gray.width(), gray.height())
+            .into_iter()
+            .flat_map(|warning| match warning.issue {
+                RoiIssue::Overlap { row, col, .. } => {
+                    vec![[warning.row, warning.col], [row, col]]
+                }
+                _ => vec![[warning.row, warning.col]],
+            })
+            .collect();
+        if let Some(rois) = self.rois.as_ref() {
+            rois.iter().for_each(|roi| {
+                let color = if flagged.contains(&[roi.row, roi.col]) {
+                    image::Rgba([255, 0, 0, 200])
+                } else {
+                    roi.group
+                        .as_ref()
+                        .and_then(|group| groups.iter().position(|g| g == group))
+                        .map(group_color)
+                        .unwrap_or(image::Rgba([255, 255, 0, 128]))
+                };
+                roi.draw_roi(gray, &font, color);
+            })
+        };
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::app::schema::RoiFile;
+    #[test]
+    fn test_rotated_positions() {
+        let mut roicol = RoiCollection {
+            nrow: 2,
+            ncol: 2,
+            x: 100,
+            y: 50,
+            xinterval: 100,
+            yinterval: 100,
+            rotate: 10.0,
+            ..Default::default()
+        };
+        roicol.update_rois();
+        let corners = |roicol: &RoiCollection| -> Vec<(u32, u32)> {
+            roicol.rois().iter().map(|roi| (roi.x, roi.y)).collect()
+        };
+        // the grid rotated about the image origin
+        assert_eq!(
+            corners(&roicol),
+            [(90, 67), (188, 84), (72, 165), (171, 182)]
+        );
+
+        // positions of the same grid before the rotation was fixed
+        roicol.migrate_legacy_rotation();
+        roicol.update_rois();
+        assert_eq!(
+            corners(&roicol),
+            [(90, 65), (188, 82), (72, 160), (171, 177)]
+        );
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem(" Plate 1/A:B* "), "Plate 1_A_B_");
+    }
+
+    #[test]
+    fn test_grid_editing() {
+        let mut roicol = RoiCollection {
+            rotate: 10.0,
+            ..Default::default()
+        };
+        let (x0, y0) = roicol.grid_point(0, 0);
+        roicol.translate(12.0, -5.0);
+        let (x1, y1) = roicol.grid_point(0, 0);
+        assert!((x1 - x0 - 12.0).abs() < 1.5 && (y1 - y0 + 5.0).abs() < 1.5);
+
+        roicol.rotate_by(-10.0);
+        assert_eq!(roicol.rotate, 0.0);
+        let (x2, y2) = roicol.grid_point(0, 0);
+        assert!((x2 - x1).abs() < 1.0 && (y2 - y1).abs() < 1.0);
+
+        roicol.stretch_to(x2 + 700.0, y2 + 500.0);
+        assert_eq!((roicol.xinterval, roicol.yinterval), (100, 100));
+
+        roicol.set_offset(1, 2, 3, -4);
+        roicol.update_rois();
+        let roi = &roicol.rois()[roicol.ncol as usize + 2];
+        assert_eq!(
+            (roi.x, roi.y),
+            ((roicol.x + 203) as u32, (roicol.y + 96) as u32)
+        );
+        assert_eq!(
+            roicol.roi_at(roi.x as f64 + 1., roi.y as f64 + 1.),
+            Some((1, 2))
+        );
+        roicol.set_offset(1, 2, 0, 0);
+        assert!(roicol.offsets.is_empty());
+    }
+
+    #[test]
+    fn test_excluded_roi() {
+        let mut roicol = RoiCollection::default();
+        roicol.set_excluded(0, 1, true);
+        roicol.update_rois();
+        assert_eq!(roicol.labels().len(), 47);
+        assert_eq!(&roicol.labels()[..2], ["0", "2"]);
+
+        let mut subimg = ImageBuffer::from_pixel(1200, 900, image::Luma([0u8]));
+        let roi = &roicol.rois()[2];
+        subimg.put_pixel(roi.x, roi.y, image::Luma([255]));
+        let area = roicol
+            .measure_mask(&binarize(&subimg, 2.0, Direction::Darker))
+            .unwrap();
+        assert_eq!(area.len(), 47);
+        let full = roicol.width * roicol.height;
+        assert_eq!((area[0], area[1]), (full, full - 1));
+
+        roicol.set_excluded(0, 1, false);
+        assert!(roicol.excluded.is_empty());
+    }
+
+    #[test]
+    fn test_fit_corners() {
+        let mut roicol = RoiCollection::default();
+        let (hw, hh) = (roicol.width as f64 / 2., roicol.height as f64 / 2.);
+        let rot = 2.0f64.to_radians();
+        let (rot_sin, rot_cos) = rot.sin_cos();
+        let at = |x: f64, y: f64| {
+            (
+                x * rot_cos - y * rot_sin + hw,
+                x * rot_sin + y * rot_cos + hh,
+            )
+        };
+        let top_left = at(100., 50.);
+        let top_right = at(100. + 7. * 120., 50.);
+        let bottom_left = at(100., 50. + 5. * 125.);
+
+        roicol.fit_corners(top_left, top_right, bottom_left, FitMode::Rotation);
+        assert_eq!((roicol.x, roicol.y), (100, 50));
+        assert_eq!((roicol.xinterval, roicol.yinterval), (120, 125));
+        assert!((roicol.rotate - 2.0).abs() < 0.01);
+
+        // near the top edge the unrotated origin is negative and kept so
+        let (rot_sin, rot_cos) = 5.0f64.to_radians().sin_cos();
+        let corner = |col: f64, row: f64| {
+            (
+                400. + col * rot_cos - row * rot_sin + hw,
+                30. + col * rot_sin + row * rot_cos + hh,
+            )
+        };
+        let (top_left, top_right, bottom_left) =
+            (corner(0., 0.), corner(7. * 120., 0.), corner(0., 5. * 125.));
+        roicol.fit_corners(top_left, top_right, bottom_left, FitMode::Rotation);
+        assert!(roicol.y < 0);
+        roicol.update_rois();
+        assert_eq!((roicol.rois()[0].x, roicol.rois()[0].y), (400, 30));
+
+        // shear: the bottom row is shifted right by 30 px
+        let bottom_left = (bottom_left.0 + 30., bottom_left.1);
+        roicol.fit_corners(top_left, top_right, bottom_left, FitMode::Affine);
+        let (x, y) = roicol.grid_point(5, 0);
+        assert!((x + hw - bottom_left.0).abs() < 1e-6 && (y + hh - bottom_left.1).abs() < 1e-6);
+        let (x, y) = roicol.grid_point(0, 7);
+        assert!((x + hw - top_right.0).abs() < 1e-6 && (y + hh - top_right.1).abs() < 1e-6);
+
+        roicol.clear_affine();
+        assert!(roicol.affine.is_none());
+    }
+
+    #[test]
+    fn test_plate_template() {
+        let mut roicol = RoiCollection::default();
+        roicol.set_excluded(5, 7, true);
+        roicol.apply_template(PlateFormat::Well24);
+        assert_eq!((roicol.nrow, roicol.ncol), (4, 6));
+        // the excluded well of the 6 x 8 grid is outside the 4 x 6 grid
+        assert!(roicol.excluded.is_empty());
+        assert!(RoiFile::new(vec![roicol]).validate().is_ok());
+
+        let mut roicol = RoiCollection::default();
+        roicol.apply_template(PlateFormat::Well96);
+        assert_eq!((roicol.nrow, roicol.ncol), (8, 12));
+        // the 96 well grid spans the same area as the default 6 x 8 grid
+        assert_eq!((roicol.xinterval, roicol.yinterval), (83, 94));
+        roicol.numbering.order = crate::app::plate::NumberingOrder::ColumnMajor;
+        roicol.update_rois();
+        let labels = roicol.labels();
+        assert_eq!(labels.len(), 96);
+        assert_eq!(&labels[..3], ["A1", "B1", "C1"]);
+        assert_eq!(labels[8], "A2");
+        assert_eq!(labels[95], "H12");
+    }
+
+    #[test]
+    fn test_custom_shapes() {
+        let mut roicol = RoiCollection {
+            custom: vec![
+                FreeRoi {
+                    name: "oval".to_owned(),
+                    x: 0,
+                    y: 0,
+                    width: 10,
+                    height: 10,
+                    shape: Shape::Oval,
+                },
+                FreeRoi {
+                    name: "triangle".to_owned(),
+                    x: 20,
+                    y: 0,
+                    width: 10,
+                    height: 10,
+                    shape: Shape::Polygon {
+                        points: vec![[20, 0], [30, 0], [20, 10]],
+                    },
+                },
+            ],
+            ..Default::default()
+        };
+        roicol.update_rois();
+        assert_eq!(roicol.labels(), ["oval", "triangle"]);
+
+        // everything is "moving" in a black difference image
+        let subimg = ImageBuffer::from_pixel(40, 20, image::Luma([0u8]));
+        let area = roicol
+            .measure_mask(&binarize(&subimg, 2.0, Direction::Darker))
+            .unwrap();
+        assert_eq!(area, [80, 45]);
+    }
+
+    #[test]
+    fn test_binarize_direction() {
+        // at 2.0 x std the cuts are 101 and 154
+        let subimg = ImageBuffer::from_fn(5, 1, |x, _| {
+            image::Luma([[0u8, 101, 127, 154, 255][x as usize]])
+        });
+        let moving = |direction| -> Vec<u8> {
+            binarize(&subimg, 2.0, direction)
+                .iter()
+                .map(|pix| pix / 255)
+                .collect()
+        };
+        assert_eq!(moving(Direction::Darker), [1, 1, 0, 0, 0]);
+        assert_eq!(moving(Direction::Brighter), [0, 0, 0, 1, 1]);
+        assert_eq!(moving(Direction::Absolute), [1, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_roi_thresholds() {
+        let mut roicol = RoiCollection {
+            nrow: 1,
+            ncol: 2,
+            x: 0,
+            y: 0,
+            xinterval: 10,
+            width: 10,
+            height: 10,
+            ..Default::default()
+        };
+        roicol.set_threshold(0, 1, Some(4.0));
+        roicol.update_rois();
+        assert_eq!(roicol.rois()[1].threshold, Some(4.0));
+
+        // 2.5 x std below the mean everywhere
+        let subimg = ImageBuffer::from_pixel(20, 10, image::Luma([95u8]));
+        let mut mask = binarize(&subimg, 2.0, Direction::Darker);
+        roicol.apply_thresholds(&subimg, &mut mask, Direction::Darker);
+        assert_eq!(roicol.measure_mask(&mask).unwrap(), [100, 0]);
+
+        roicol.set_threshold(0, 1, None);
+        assert!(roicol.thresholds.is_empty());
+    }
+
+    #[test]
+    fn test_roi_check() {
+        let mut roicol = RoiCollection {
+            nrow: 1,
+            ncol: 3,
+            x: 0,
+            y: 0,
+            xinterval: 10,
+            width: 12,
+            height: 10,
+            ..Default::default()
+        };
+        roicol.update_rois();
+
+        // ROIs are 12 px wide every 10 px, on a 25 x 8 image
+        let warnings = roicol.check(25, 8);
+        let text: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "ROI 0: clipped by the image border, 96 of 120 px measured",
+                "ROI 0: overlaps ROI 1",
+                "ROI 1: clipped by the image border, 96 of 120 px measured",
+                "ROI 1: overlaps ROI 2",
+                "ROI 2: clipped by the image border, 40 of 120 px measured",
+            ]
+        );
+
+        roicol.set_excluded(0, 1, true);
+        roicol.update_rois();
+        assert!(roicol.check(40, 10).is_empty());
+        assert_eq!(roicol.check(15, 10)[0].issue, RoiIssue::Empty);
+        // ROI 2 lies fully outside the image and measures nothing
+        let mask = ImageBuffer::from_pixel(15, 10, image::Luma([255u8]));
+        assert_eq!(roicol.measure_mask(&mask), Some(vec![120, 0]));
+    }
+
     #[test]
     fn test_new_roi() {
         let mut roicol = RoiCollection::default();
         roicol.update_rois();
-        roicol
+        RoiFile::new(vec![roicol])
             .to_json("./test.json")
             .expect("fail to write to json");
     }
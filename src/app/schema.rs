@@ -0,0 +1,295 @@
+//! Versioned layout of Roi.json, with migration of older files and
+//! validation of their contents. `schema/roi.schema.json` documents the
+//! current version for other tools.
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use super::roi::{RoiCollection, Shape};
+
+/// Current version of the Roi.json layout.
+///
+/// 1. unversioned: a single grid at the top level. A rotated grid was placed
+///    with the rotation bug fixed since, and is migrated to the affine grid of
+///    its old positions.
+/// 2. `version` and `collections` at the top level, for any number of
+///    collections.
+pub const VERSION: u32 = 2;
+
+/// Contents of Roi.json.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RoiFile {
+    pub version: u32,
+    pub collections: Vec<RoiCollection>,
+}
+
+/// Problems found in a Roi.json file, one message per line.
+#[derive(Debug)]
+pub struct ValidationError(pub Vec<String>);
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+impl Error for ValidationError {}
+
+impl RoiFile {
+    pub fn new(collections: Vec<RoiCollection>) -> Self {
+        Self {
+            version: VERSION,
+            collections,
+        }
+    }
+
+    /// Parse a Roi.json of any supported version and validate it.
+    pub fn from_json(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let value: serde_json::Value =
+            serde_json::from_str(contents).map_err(|e| format!("invalid JSON: {e}"))?;
+        let object = value
+            .as_object()
+            .ok_or("Roi.json must contain a JSON object")?;
+        // the text is parsed rather than the value to keep line numbers
+        let file = match object.get("version") {
+            None => {
+                let mut roicol: RoiCollection = serde_json::from_str(contents)?;
+                roicol.migrate_legacy_rotation();
+                Self::new(vec![roicol])
+            }
+            Some(version) => match version.as_u64() {
+                Some(v) if v == VERSION as u64 => serde_json::from_str(contents)?,
+                Some(v) if v > VERSION as u64 => {
+                    return Err(format!(
+                        "Roi.json version {v} is newer than the supported version {VERSION}, please update soustraire"
+                    )
+                    .into())
+                }
+                _ => return Err(format!("unsupported Roi.json version: {version}").into()),
+            },
+        };
+        file.validate()?;
+        Ok(file)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut errors = vec![];
+        if self.collections.is_empty() {
+            errors.push("`collections` must contain at least one collection".to_owned());
+        }
+        let multiple = self.collections.len() > 1;
+        let mut names = HashSet::new();
+        for (idx, roicol) in self.collections.iter().enumerate() {
+            let prefix = if roicol.name.is_empty() {
+                format!("collection {}", idx + 1)
+            } else {
+                format!("collection `{}`", roicol.name)
+            };
+            if multiple && roicol.name.is_empty() {
+                errors.push(format!(
+                    "{prefix}: `name` is required with several collections"
+                ));
+            } else if multiple && !names.insert(roicol.name.as_str()) {
+                errors.push(format!("{prefix}: `name` is used by another collection"));
+            }
+            errors.extend(
+                validate_collection(roicol)
+                    .into_iter()
+                    .map(|e| format!("{prefix}: {e}")),
+            );
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(errors))
+        }
+    }
+
+    pub fn into_collections(self) -> Vec<RoiCollection> {
+        if self.collections.is_empty() {
+            return vec![RoiCollection::default()];
+        }
+        self.collections
+    }
+}
+
+fn validate_collection(roicol: &RoiCollection) -> Vec<String> {
+    let mut errors = vec![];
+    // grid positions that excluded ROIs and offsets may refer to
+    let (nrow, ncol) = if roicol.custom.is_empty() {
+        if roicol.nrow == 0 || roicol.ncol == 0 {
+            errors.push(format!(
+                "the grid has {} rows and {} columns, at least 1 of each is required",
+                roicol.nrow, roicol.ncol
+            ));
+        }
+        if roicol.width == 0 || roicol.height == 0 {
+            errors.push(format!(
+                "ROI size {}x{} must be at least 1x1 px",
+                roicol.width, roicol.height
+            ));
+        }
+        if !(-90.0..=90.0).contains(&roicol.rotate) {
+            errors.push(format!(
+                "`rotate` {} must be within -90 and 90 degrees",
+                roicol.rotate
+            ));
+        }
+        (roicol.nrow, roicol.ncol)
+    } else {
+        for (k, free) in roicol.custom.iter().enumerate() {
+            if free.width == 0 || free.height == 0 {
+                errors.push(format!(
+                    "custom ROI {}: size must be at least 1x1 px",
+                    k + 1
+                ));
+            }
+            if let Shape::Polygon { points } = &free.shape {
+                if points.len() < 3 {
+                    errors.push(format!(
+                        "custom ROI {}: a polygon needs at least 3 points",
+                        k + 1
+                    ));
+                }
+            }
+        }
+        (1, roicol.custom.len() as u32)
+    };
+    if roicol.numbering.start > 1 {
+        errors.push(format!(
+            "`numbering.start` {} must be 0 or 1",
+            roicol.numbering.start
+        ));
+    }
+    for [row, col] in &roicol.excluded {
+        if *row >= nrow || *col >= ncol {
+            errors.push(format!(
+                "excluded ROI [{row}, {col}] is outside of the grid"
+            ));
+        }
+    }
+    for offset in &roicol.offsets {
+        if offset.row >= nrow || offset.col >= ncol {
+            errors.push(format!(
+                "offset of ROI [{}, {}] is outside of the grid",
+                offset.row, offset.col
+            ));
+        }
+    }
+    for group in &roicol.grouping {
+        if group.row >= nrow || group.col >= ncol {
+            errors.push(format!(
+                "group of ROI [{}, {}] is outside of the grid",
+                group.row, group.col
+            ));
+        }
+    }
+    for threshold in &roicol.thresholds {
+        if threshold.row >= nrow || threshold.col >= ncol {
+            errors.push(format!(
+                "threshold of ROI [{}, {}] is outside of the grid",
+                threshold.row, threshold.col
+            ));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str = r#"{
+        "nrow": 2, "ncol": 3, "x": 0, "y": 0, "xinterval": 10, "yinterval": 10,
+        "width": 8, "height": 8, "rotate": 0.0, "excluded": [[1, 2]]
+    }"#;
+
+    #[test]
+    fn test_migration() {
+        let file = RoiFile::from_json(LEGACY).expect("fail to migrate Roi.json");
+        assert_eq!(file.version, VERSION);
+        assert_eq!(file.collections[0].ncol, 3);
+
+        let mut right = file.collections[0].clone();
+        right.name = "right".to_owned();
+        let mut left = file.collections[0].clone();
+        left.name = "left".to_owned();
+        let json = serde_json::to_string(&RoiFile::new(vec![left, right])).unwrap();
+        let collections = RoiFile::from_json(&json).unwrap().into_collections();
+        assert_eq!(collections[1].name, "right");
+
+        // a rotated grid keeps the positions of the old rotation
+        let rotated = LEGACY.replace(r#""rotate": 0.0"#, r#""rotate": 10.0"#);
+        let roicol = &RoiFile::from_json(&rotated).unwrap().collections[0];
+        assert!(roicol.affine.is_some());
+        assert!(RoiFile::from_json(LEGACY).unwrap().collections[0]
+            .affine
+            .is_none());
+    }
+
+    #[test]
+    fn test_validation() {
+        let typo = LEGACY.replace("xinterval", "xintervall");
+        let err = RoiFile::from_json(&typo).unwrap_err().to_string();
+        assert!(err.contains("unknown field `xintervall`"), "{err}");
+        assert!(err.contains("line 2"), "{err}");
+
+        let invalid = LEGACY
+            .replace("[[1, 2]]", "[[2, 0]]")
+            .replace("\"width\": 8", "\"width\": 0");
+        let err = RoiFile::from_json(&invalid).unwrap_err().to_string();
+        assert_eq!(
+            err.lines().collect::<Vec<_>>(),
+            [
+                "collection 1: ROI size 0x8 must be at least 1x1 px",
+                "collection 1: excluded ROI [2, 0] is outside of the grid",
+            ]
+        );
+
+        let newer = format!(r#"{{"version": {}, "collections": []}}"#, VERSION + 1);
+        assert!(RoiFile::from_json(&newer).is_err());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(include_str!("../../schema/roi.schema.json"))
+                .expect("roi.schema.json is not valid JSON");
+        assert_eq!(schema["properties"]["version"]["const"], VERSION);
+
+        // every field written by soustraire is described by the schema
+        let mut roicol = RoiCollection::default();
+        roicol.update_rois();
+        let file = serde_json::to_value(RoiFile::new(vec![roicol])).unwrap();
+        let properties = &schema["$defs"]["collection"]["properties"];
+        for key in file["collections"][0].as_object().unwrap().keys() {
+            assert!(
+                properties.get(key).is_some(),
+                "`{key}` is not in the schema"
+            );
+        }
+        let roi_properties = &schema["$defs"]["roi"]["properties"];
+        for key in file["collections"][0]["rois"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+        {
+            assert!(
+                roi_properties.get(key).is_some(),
+                "`{key}` is not in the schema"
+            );
+        }
+    }
+}
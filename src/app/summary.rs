@@ -0,0 +1,285 @@
+use std::error::Error;
+use std::path::Path;
+
+use itertools::Itertools;
+
+use super::export::NA;
+use super::roi::{RoiCollection, RoiGroup};
+
+/// Descriptive statistics of one group at one time-point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupStats {
+    pub n: usize,
+    pub mean: f64,
+    /// Standard error of the mean, `NaN` when `n < 2`.
+    pub sem: f64,
+    pub median: f64,
+}
+
+impl GroupStats {
+    pub fn new(values: &[f64]) -> Self {
+        let n = values.len();
+        if n == 0 {
+            return Self {
+                n,
+                mean: f64::NAN,
+                sem: f64::NAN,
+                median: f64::NAN,
+            };
+        }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let sem = if n > 1 {
+            let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            (var / n as f64).sqrt()
+        } else {
+            f64::NAN
+        };
+        let sorted: Vec<f64> = values.iter().copied().sorted_by(f64::total_cmp).collect();
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.
+        };
+        Self {
+            n,
+            mean,
+            sem,
+            median,
+        }
+    }
+}
+
+/// Per-frame statistics of every ROI group.
+#[derive(Debug, Clone, Default)]
+pub struct GroupSummary {
+    pub groups: Vec<String>,
+    pub frames: Vec<usize>,
+    /// `stats[frame][group]`
+    pub stats: Vec<Vec<GroupStats>>,
+}
+
+impl GroupSummary {
+    /// Aggregate the areas of the measured ROIs by their group; excluded
+    /// frames (`None`) have no value.
+    pub fn new(roicol: &RoiCollection, frames: &[(usize, Option<Vec<u32>>)]) -> Option<Self> {
+        let groups = roicol.groups();
+        if groups.is_empty() {
+            return None;
+        }
+        let members: Vec<Option<usize>> = roicol
+            .measured()
+            .map(|roi| {
+                roi.group
+                    .as_ref()
+                    .and_then(|group| groups.iter().position(|g| g == group))
+            })
+            .collect();
+
+        let stats = frames
+            .iter()
+            .map(|(_, area)| {
+                (0..groups.len())
+                    .map(|g| {
+                        let values: Vec<f64> = members
+                            .iter()
+                            .zip(area.iter().flatten())
+                            .filter(|(member, _)| **member == Some(g))
+                            .map(|(_, v)| *v as f64)
+                            .collect();
+                        GroupStats::new(&values)
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(Self {
+            groups,
+            frames: frames.iter().map(|(frame, _)| *frame).collect(),
+            stats,
+        })
+    }
+
+    pub fn to_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["frame", "group", "n", "mean", "sem", "median"])?;
+        for (frame, stats) in self.frames.iter().zip(&self.stats) {
+            for (group, s) in self.groups.iter().zip(stats) {
+                // excluded frames and the sem of a single ROI, as in the
+                // other tables
+                let value = |v: f64| {
+                    if v.is_nan() {
+                        NA.to_owned()
+                    } else {
+                        v.to_string()
+                    }
+                };
+                writer.write_record([
+                    frame.to_string(),
+                    group.clone(),
+                    s.n.to_string(),
+                    value(s.mean),
+                    value(s.sem),
+                    value(s.median),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Combine the summaries of several ROI collections measured on the same
+    /// frames, prefixing each group with the name of its collection.
+    pub fn merge(summaries: Vec<(String, GroupSummary)>) -> Option<Self> {
+        let mut merged: Option<Self> = None;
+        for (name, summary) in summaries {
+            let groups = summary
+                .groups
+                .iter()
+                .map(|group| format!("{name}: {group}"));
+            match merged.as_mut() {
+                None => {
+                    merged = Some(Self {
+                        groups: groups.collect(),
+                        ..summary
+                    })
+                }
+                Some(merged) => {
+                    merged.groups.extend(groups);
+                    merged
+                        .stats
+                        .iter_mut()
+                        .zip(summary.stats)
+                        .for_each(|(stats, other)| stats.extend(other));
+                }
+            }
+        }
+        merged
+    }
+
+    /// `(frame, mean)` trace of the `idx`-th group.
+    pub fn mean_trace(&self, idx: usize) -> Vec<[f64; 2]> {
+        self.frames
+            .iter()
+            .zip(&self.stats)
+            .map(|(frame, stats)| [*frame as f64, stats[idx].mean])
+            .collect()
+    }
+}
+
+/// Read a ROI grouping CSV with a `roi` column (the ROI label shown on the
+/// image) and a `group` column, for the ROIs of `roicol`.
+pub fn read_grouping<P: AsRef<Path>>(
+    path: P,
+    roicol: &RoiCollection,
+) -> Result<Vec<RoiGroup>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = rdr.headers()?.iter().map(|h| h.to_lowercase()).collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let roi = column("roi")
+        .or_else(|| column("index"))
+        .ok_or("grouping has no `roi` column")?;
+    let group = column("group").ok_or("grouping has no `group` column")?;
+
+    let mut grouping = vec![];
+    for (line, record) in rdr.records().enumerate() {
+        let record = record?;
+        let name = record.get(group).unwrap_or_default();
+        if name.is_empty() {
+            continue;
+        }
+        let label = record.get(roi).unwrap_or_default();
+        let found = roicol
+            .rois()
+            .iter()
+            .find(|roi| roi.label == label)
+            .ok_or_else(|| format!("line {}: no ROI is labelled `{label}`", line + 2))?;
+        grouping.retain(|g: &RoiGroup| g.row != found.row || g.col != found.col);
+        grouping.push(RoiGroup {
+            row: found.row,
+            col: found.col,
+            group: name.to_owned(),
+        });
+    }
+    Ok(grouping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_stats() {
+        let s = GroupStats::new(&[1., 2., 3., 10.]);
+        assert_eq!(s.n, 4);
+        assert_eq!(s.mean, 4.);
+        assert_eq!(s.median, 2.5);
+        assert!((s.sem - 2.0412414523193148).abs() < 1e-12);
+        assert!(GroupStats::new(&[5.]).sem.is_nan());
+    }
+
+    #[test]
+    fn test_group_summary() {
+        let mut roicol = RoiCollection::default();
+        roicol.nrow = 1;
+        roicol.ncol = 4;
+        roicol.grouping = [(0, "wt"), (1, "wt"), (2, "mut")]
+            .map(|(col, group)| RoiGroup {
+                row: 0,
+                col,
+                group: group.to_owned(),
+            })
+            .to_vec();
+        roicol.set_excluded(0, 1, true);
+        roicol.update_rois();
+
+        let frames = vec![
+            (1, Some(vec![10, 30, 40])),
+            (2, Some(vec![20, 50, 60])),
+            (3, None),
+        ];
+        let summary = GroupSummary::new(&roicol, &frames).unwrap();
+        assert_eq!(summary.groups, ["mut", "wt"]);
+        // ROI 1 is excluded, ROI 3 has no group
+        assert_eq!(summary.stats[0][1].n, 1);
+        assert_eq!(summary.stats[0][0].mean, 30.);
+        assert_eq!(summary.mean_trace(1)[..2], [[1., 10.], [2., 20.]]);
+        // an excluded frame has no value
+        assert_eq!(summary.stats[2][1].n, 0);
+        assert!(summary.mean_trace(1)[2][1].is_nan());
+
+        let path = std::env::temp_dir().join(format!("summary-{}.csv", std::process::id()));
+        summary.to_csv(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let rows: Vec<&str> = contents.lines().collect();
+        assert_eq!(rows[1], "1,mut,1,30,NA,30");
+        assert_eq!(rows[5], "3,mut,0,NA,NA,NA");
+        assert_eq!(rows[6], "3,wt,0,NA,NA,NA");
+    }
+
+    #[test]
+    fn test_read_grouping() {
+        let mut roicol = RoiCollection::default();
+        roicol.nrow = 1;
+        roicol.ncol = 3;
+        roicol.numbering.start = 1;
+        roicol.update_rois();
+        let path = std::env::temp_dir().join(format!("grouping-{}.csv", std::process::id()));
+        std::fs::write(&path, "roi,group\n1,wt\n3,mut\n").unwrap();
+        roicol.grouping = read_grouping(&path, &roicol).unwrap();
+        // the groups stay with their ROIs when the numbering changes
+        roicol.numbering.start = 0;
+        roicol.update_rois();
+        let groups: Vec<_> = roicol.rois().iter().map(|roi| roi.group.clone()).collect();
+        assert_eq!(
+            groups,
+            [Some("wt".to_owned()), None, Some("mut".to_owned())]
+        );
+
+        std::fs::write(&path, "roi,group\n4,wt\n").unwrap();
+        let err = read_grouping(&path, &roicol).unwrap_err().to_string();
+        assert_eq!(err, "line 2: no ROI is labelled `4`");
+        std::fs::remove_file(&path).ok();
+    }
+}
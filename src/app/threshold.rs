@@ -0,0 +1,272 @@
+//! Automatic selection of the binarization threshold from the histogram of
+//! a normalized difference image.
+use image::{ImageBuffer, Luma};
+use serde::{Deserialize, Serialize};
+
+use super::roi::Direction;
+
+type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
+
+/// How the binarization threshold is chosen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThresholdMethod {
+    /// The value set in the side panel.
+    #[default]
+    Manual,
+    /// Otsu's split of the histogram into two classes.
+    Otsu,
+    /// The value exceeded by the given share of the pixels.
+    Percentile,
+    /// Triangle method, for a noise peak with a long tail of motion.
+    Triangle,
+}
+
+impl std::fmt::Display for ThresholdMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThresholdMethod::Manual => write!(f, "Manual"),
+            ThresholdMethod::Otsu => write!(f, "Otsu"),
+            ThresholdMethod::Percentile => write!(f, "Noise percentile"),
+            ThresholdMethod::Triangle => write!(f, "Triangle"),
+        }
+    }
+}
+
+/// Settings of the automatic threshold.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct AutoThreshold {
+    pub method: ThresholdMethod,
+    /// Select the threshold once for the run instead of for every frame.
+    pub per_run: bool,
+    /// Percentile of the deviations that is still noise, for
+    /// [`ThresholdMethod::Percentile`].
+    pub percentile: f64,
+    /// Lowest threshold selected, in std, so that a frame without motion
+    /// does not split its noise into a moving class.
+    pub floor: f64,
+}
+
+impl Default for AutoThreshold {
+    fn default() -> Self {
+        Self {
+            method: ThresholdMethod::Manual,
+            per_run: false,
+            percentile: 99.0,
+            floor: 2.0,
+        }
+    }
+}
+
+/// Histogram of the gray levels oriented in the motion `direction`: bin `k`
+/// deviates by `k - 127.5` from the mean (127.5) towards motion, so that
+/// larger bins are more likely moving.
+fn deviations(subimg: &GrayImage, direction: Direction) -> [u64; 256] {
+    let mut hist = [0u64; 256];
+    for pix in subimg.iter() {
+        let k = match direction {
+            Direction::Darker => 255 - pix,
+            Direction::Brighter => *pix,
+            Direction::Absolute => (*pix).max(255 - pix),
+        };
+        hist[k as usize] += 1;
+    }
+    hist
+}
+
+/// First bin of the upper class maximizing the between-class variance.
+fn otsu(hist: &[u64]) -> usize {
+    let total: u64 = hist.iter().sum();
+    let sum: f64 = hist
+        .iter()
+        .enumerate()
+        .map(|(k, n)| k as f64 * *n as f64)
+        .sum();
+    let (mut weight, mut sum_low) = (0u64, 0f64);
+    let (mut best, mut cut) = (-1f64, hist.len());
+    for (k, n) in hist.iter().enumerate() {
+        weight += n;
+        sum_low += k as f64 * *n as f64;
+        if weight == 0 || weight == total {
+            continue;
+        }
+        let (w0, w1) = (weight as f64, (total - weight) as f64);
+        let (m0, m1) = (sum_low / w0, (sum - sum_low) / w1);
+        let between = w0 * w1 * (m0 - m1).powi(2);
+        if between > best {
+            (best, cut) = (between, k + 1);
+        }
+    }
+    cut
+}
+
+/// First bin above the `percentile` of the values.
+fn percentile(hist: &[u64], percentile: f64) -> usize {
+    let total: u64 = hist.iter().sum();
+    let rank = (total as f64 * percentile.clamp(0., 100.) / 100.).ceil() as u64;
+    let mut acc = 0;
+    for (k, n) in hist.iter().enumerate() {
+        acc += n;
+        if acc >= rank {
+            return k + 1;
+        }
+    }
+    hist.len()
+}
+
+/// Bin farthest below the line from the peak to the end of the tail on the
+/// moving side, plus one.
+fn triangle(hist: &[u64]) -> usize {
+    // first of the highest bins
+    let Some((peak, &height)) = hist
+        .iter()
+        .enumerate()
+        .max_by(|(a, na), (b, nb)| na.cmp(nb).then(b.cmp(a)))
+    else {
+        return hist.len();
+    };
+    let end = hist.iter().rposition(|n| *n > 0).unwrap_or(peak);
+    // distance below the line, up to a constant factor
+    let (dx, dy) = ((end - peak) as f64, height as f64);
+    let dist = |k: usize| dy * (end - k) as f64 - dx * hist[k] as f64;
+    let mut cut = peak;
+    for k in peak..=end {
+        if dist(k) > dist(cut) {
+            cut = k;
+        }
+    }
+    cut + 1
+}
+
+impl AutoThreshold {
+    /// Threshold in std of a normalized difference image, `None` when it is
+    /// set manually. It is at least [`AutoThreshold::floor`], and never below
+    /// 0, i.e. never counts the other side of the mean.
+    pub fn select(&self, subimg: &GrayImage, direction: Direction) -> Option<f64> {
+        let hist = deviations(subimg, direction);
+        let cut = match self.method {
+            ThresholdMethod::Manual => return None,
+            ThresholdMethod::Otsu => otsu(&hist),
+            ThresholdMethod::Percentile => percentile(&hist, self.percentile),
+            ThresholdMethod::Triangle => triangle(&hist),
+        };
+        // `binarize` counts the bins from `128 + 12.8 x threshold` on
+        let floor = self.floor.clamp(0., 10.);
+        Some(((cut as f64 - 128.) / 12.8).clamp(floor, 10.))
+    }
+
+    /// Median of the thresholds selected on several frames, for a run.
+    pub fn calibrate(mut thresholds: Vec<f64>) -> Option<f64> {
+        thresholds.sort_by(|a, b| a.total_cmp(b));
+        thresholds.get(thresholds.len() / 2).copied()
+    }
+}
+
+/// Threshold as written in file names, e.g. `2.0` or `2.25`.
+pub fn label(threshold: f64) -> String {
+    if threshold.fract() == 0. {
+        format!("{threshold:.1}")
+    } else {
+        threshold.to_string()
+    }
+}
+
+/// Comma separated thresholds, e.g. `1.5, 2.0, 2.5`.
+pub fn parse_list(text: &str) -> Result<Vec<f64>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.parse::<f64>() {
+            Ok(t) if (-10.0..=10.0).contains(&t) => Ok(t),
+            _ => Err(format!("`{item}` is not a threshold within -10 and 10")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::roi::binarize;
+
+    #[test]
+    fn test_auto_threshold() {
+        // noise around the mean and 200 clearly darker pixels
+        let subimg = GrayImage::from_fn(100, 100, |x, y| {
+            let k = y * 100 + x;
+            Luma([if k < 200 {
+                40
+            } else {
+                [126, 127, 128, 129][k as usize % 4]
+            }])
+        });
+        let moving = |threshold| {
+            binarize(&subimg, threshold, Direction::Darker)
+                .iter()
+                .filter(|v| **v > 0)
+                .count()
+        };
+        for method in [ThresholdMethod::Otsu, ThresholdMethod::Triangle] {
+            let auto = AutoThreshold {
+                method,
+                ..Default::default()
+            };
+            let threshold = auto.select(&subimg, Direction::Darker).unwrap();
+            assert_eq!(moving(threshold), 200, "{method}");
+        }
+
+        let auto = AutoThreshold {
+            method: ThresholdMethod::Percentile,
+            percentile: 98.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            moving(auto.select(&subimg, Direction::Darker).unwrap()),
+            200
+        );
+        assert_eq!(
+            AutoThreshold::default().select(&subimg, Direction::Darker),
+            None
+        );
+        assert_eq!(AutoThreshold::calibrate(vec![3., 1., 2.]), Some(2.));
+    }
+
+    #[test]
+    fn test_auto_threshold_noise() {
+        // normalized noise without motion: mean 127.5 and std 12.8
+        let mut state = 12345u32;
+        let mut uniform = || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f64 / (1 << 24) as f64
+        };
+        let subimg = GrayImage::from_fn(100, 100, |_, _| {
+            let normal = (0..12).map(|_| uniform()).sum::<f64>() - 6.;
+            Luma([(127.5 + 12.8 * normal).round().clamp(0., 255.) as u8])
+        });
+        for method in [
+            ThresholdMethod::Otsu,
+            ThresholdMethod::Percentile,
+            ThresholdMethod::Triangle,
+        ] {
+            let auto = AutoThreshold {
+                method,
+                percentile: 50.0,
+                ..Default::default()
+            };
+            let threshold = auto.select(&subimg, Direction::Darker).unwrap();
+            assert!(threshold >= 2.0, "{method}: {threshold}");
+            let moving = binarize(&subimg, threshold, Direction::Darker)
+                .iter()
+                .filter(|v| **v > 0)
+                .count();
+            assert!(moving < 500, "{method}: {moving} moving pixels");
+        }
+    }
+
+    #[test]
+    fn test_sweep_list() {
+        assert_eq!(parse_list(" 1.5, 2,2.5 ,"), Ok(vec![1.5, 2.0, 2.5]));
+        assert_eq!(parse_list(""), Ok(vec![]));
+        assert!(parse_list("2, x").is_err());
+        assert_eq!([label(2.), label(2.25)], ["2.0", "2.25"]);
+    }
+}
@@ -0,0 +1,55 @@
+use eframe::egui;
+
+/// Strip under the frame slider with colored ranges of frames; clicking or
+/// dragging on it moves to a frame.
+fn timeline_ui(
+    ui: &mut egui::Ui,
+    pos: &mut usize,
+    len: usize,
+    marks: &[(usize, usize, egui::Color32)],
+) -> egui::Response {
+    let desired_size = egui::vec2(ui.available_width(), 0.5 * ui.spacing().interact_size.y);
+    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click_and_drag());
+    if len == 0 {
+        return response;
+    }
+    // left edge of frame `k`, frames are equally wide
+    let x = |k: usize| egui::lerp(rect.left()..=rect.right(), k as f32 / len as f32);
+    if let Some(pointer) = response.interact_pointer_pos() {
+        let frame = ((pointer.x - rect.left()) / rect.width() * len as f32).floor();
+        let frame = (frame.max(0.) as usize).min(len - 1);
+        if frame != *pos {
+            *pos = frame;
+            response.mark_changed();
+        }
+    }
+
+    if ui.is_rect_visible(rect) {
+        let visuals = ui.style().noninteractive();
+        ui.painter()
+            .rect(rect, 0., visuals.bg_fill, visuals.bg_stroke);
+        for &(first, last, color) in marks {
+            let mark = egui::Rect::from_x_y_ranges(
+                x(first)..=x(last + 1).max(x(first) + 1.),
+                rect.y_range(),
+            );
+            ui.painter().rect_filled(mark, 0., color);
+        }
+        let cursor = x(*pos) + 0.5 * (x(*pos + 1) - x(*pos));
+        ui.painter().vline(
+            cursor,
+            rect.y_range(),
+            ui.style().visuals.widgets.active.fg_stroke,
+        );
+    }
+
+    response
+}
+
+pub fn timeline<'a>(
+    pos: &'a mut usize,
+    len: usize,
+    marks: &'a [(usize, usize, egui::Color32)],
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| timeline_ui(ui, pos, len, marks)
+}